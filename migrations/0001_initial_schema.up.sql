@@ -0,0 +1,32 @@
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    nick TEXT NOT NULL,
+    email TEXT NOT NULL,
+    password TEXT NOT NULL,
+    salt BYTEA NOT NULL,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    nb_pictures INTEGER NOT NULL DEFAULT 0,
+    hypes INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE pictures (
+    id SERIAL PRIMARY KEY,
+    author TEXT NOT NULL REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+    description TEXT NOT NULL DEFAULT '',
+    gps_lat DOUBLE PRECISION NOT NULL,
+    gps_long DOUBLE PRECISION NOT NULL,
+    date_taken DATE NOT NULL,
+    rating REAL,
+    likes INTEGER NOT NULL DEFAULT 0,
+    uploaded BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX pictures_location_idx ON pictures (gps_long, gps_lat) WHERE uploaded;
+
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+DROP INDEX sessions_username_idx;
+
+ALTER TABLE sessions DROP COLUMN last_seen;
@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN last_seen TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+
+CREATE INDEX sessions_username_idx ON sessions (username);
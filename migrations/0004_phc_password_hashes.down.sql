@@ -0,0 +1,4 @@
+-- rows hashed since then keep their PHC hash, they need a password reset
+UPDATE users SET salt = '' WHERE salt IS NULL;
+
+ALTER TABLE users ALTER COLUMN salt SET NOT NULL;
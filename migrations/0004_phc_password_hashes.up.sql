@@ -0,0 +1,2 @@
+-- PHC hashes embed their salt, only legacy rows keep one here
+ALTER TABLE users ALTER COLUMN salt DROP NOT NULL;
@@ -0,0 +1,2 @@
+DROP INDEX pictures_geohash_idx;
+ALTER TABLE pictures DROP COLUMN geohash;
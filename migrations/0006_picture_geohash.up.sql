@@ -0,0 +1,60 @@
+ALTER TABLE pictures ADD COLUMN geohash TEXT;
+
+-- same encoding as geo::geohash::encode, only needed to backfill existing rows
+CREATE FUNCTION pg_temp.geohash_encode(lat DOUBLE PRECISION, long DOUBLE PRECISION, prec INTEGER)
+RETURNS TEXT AS $$
+DECLARE
+    alphabet TEXT := '0123456789bcdefghjkmnpqrstuvwxyz';
+    lat_min DOUBLE PRECISION := -90;
+    lat_max DOUBLE PRECISION := 90;
+    long_min DOUBLE PRECISION := -180;
+    long_max DOUBLE PRECISION := 180;
+    mid DOUBLE PRECISION;
+    is_long_bit BOOLEAN := TRUE;
+    bits INTEGER := 0;
+    value INTEGER := 0;
+    hash TEXT := '';
+BEGIN
+    WHILE length(hash) < prec LOOP
+        IF is_long_bit THEN
+            mid := (long_min + long_max) / 2;
+            IF long >= mid THEN
+                value := value * 2 + 1;
+                long_min := mid;
+            ELSE
+                value := value * 2;
+                long_max := mid;
+            END IF;
+        ELSE
+            mid := (lat_min + lat_max) / 2;
+            IF lat >= mid THEN
+                value := value * 2 + 1;
+                lat_min := mid;
+            ELSE
+                value := value * 2;
+                lat_max := mid;
+            END IF;
+        END IF;
+
+        is_long_bit := NOT is_long_bit;
+        bits := bits + 1;
+
+        IF bits = 5 THEN
+            hash := hash || substr(alphabet, value + 1, 1);
+            bits := 0;
+            value := 0;
+        END IF;
+    END LOOP;
+
+    RETURN hash;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+UPDATE pictures SET geohash = pg_temp.geohash_encode(gps_lat, gps_long, 9);
+
+DROP FUNCTION pg_temp.geohash_encode(DOUBLE PRECISION, DOUBLE PRECISION, INTEGER);
+
+ALTER TABLE pictures ALTER COLUMN geohash SET NOT NULL;
+
+-- text_pattern_ops so that `geohash LIKE 'prefix%'` can use the index
+CREATE INDEX pictures_geohash_idx ON pictures (geohash text_pattern_ops) WHERE uploaded;
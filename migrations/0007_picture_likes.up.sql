@@ -0,0 +1,9 @@
+CREATE TABLE picture_likes (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+    picture_id INTEGER NOT NULL REFERENCES pictures (id) ON DELETE CASCADE,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT picture_likes_username_picture_id_key UNIQUE (username, picture_id)
+);
+
+CREATE INDEX picture_likes_picture_id_idx ON picture_likes (picture_id);
@@ -0,0 +1,2 @@
+ALTER TABLE pictures DROP COLUMN rating_count;
+DROP TABLE picture_ratings;
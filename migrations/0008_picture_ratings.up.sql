@@ -0,0 +1,23 @@
+CREATE TABLE picture_ratings (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+    picture_id INTEGER NOT NULL REFERENCES pictures (id) ON DELETE CASCADE,
+    score REAL NOT NULL CHECK (score BETWEEN 0 AND 5),
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    date_updated TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT picture_ratings_username_picture_id_key UNIQUE (username, picture_id)
+);
+
+CREATE INDEX picture_ratings_picture_id_idx ON picture_ratings (picture_id);
+
+ALTER TABLE pictures ADD COLUMN rating_count INTEGER NOT NULL DEFAULT 0;
+
+-- the rating set by the uploader at creation becomes their vote
+INSERT INTO picture_ratings (username, picture_id, score)
+    SELECT author, id, LEAST(GREATEST(rating, 0), 5)
+    FROM pictures
+    WHERE rating IS NOT NULL;
+
+UPDATE pictures
+    SET rating = LEAST(GREATEST(rating, 0), 5), rating_count = 1
+    WHERE rating IS NOT NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE pictures DROP COLUMN byte_size;
+ALTER TABLE pictures DROP COLUMN height;
+ALTER TABLE pictures DROP COLUMN width;
+ALTER TABLE pictures DROP COLUMN mime_type;
@@ -0,0 +1,4 @@
+ALTER TABLE pictures ADD COLUMN mime_type TEXT;
+ALTER TABLE pictures ADD COLUMN width INTEGER;
+ALTER TABLE pictures ADD COLUMN height INTEGER;
+ALTER TABLE pictures ADD COLUMN byte_size BIGINT;
@@ -0,0 +1,7 @@
+ALTER TABLE pictures DROP COLUMN location_mismatch;
+ALTER TABLE pictures DROP COLUMN exif_gps_long;
+ALTER TABLE pictures DROP COLUMN exif_gps_lat;
+ALTER TABLE pictures DROP COLUMN claimed_gps_long;
+ALTER TABLE pictures DROP COLUMN claimed_gps_lat;
+ALTER TABLE pictures DROP COLUMN camera_model;
+ALTER TABLE pictures DROP COLUMN orientation;
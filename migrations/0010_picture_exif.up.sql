@@ -0,0 +1,12 @@
+ALTER TABLE pictures ADD COLUMN orientation SMALLINT;
+ALTER TABLE pictures ADD COLUMN camera_model TEXT;
+-- location the client claimed, kept apart since the embedded one may replace gps_lat/gps_long
+ALTER TABLE pictures ADD COLUMN claimed_gps_lat DOUBLE PRECISION;
+ALTER TABLE pictures ADD COLUMN claimed_gps_long DOUBLE PRECISION;
+UPDATE pictures SET claimed_gps_lat = gps_lat, claimed_gps_long = gps_long;
+ALTER TABLE pictures ALTER COLUMN claimed_gps_lat SET NOT NULL;
+ALTER TABLE pictures ALTER COLUMN claimed_gps_long SET NOT NULL;
+-- location embedded in the upload, kept to cross-check the one the client claimed
+ALTER TABLE pictures ADD COLUMN exif_gps_lat DOUBLE PRECISION;
+ALTER TABLE pictures ADD COLUMN exif_gps_long DOUBLE PRECISION;
+ALTER TABLE pictures ADD COLUMN location_mismatch BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,369 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use nickel::{Request, Response, Middleware, MiddlewareResult, Continue};
+use plugin::Extensible;
+use typemap::Key;
+use toml;
+
+/// Where the configuration is read from when neither `--config`
+/// nor `HYPEST_CONFIG` is given. It may be missing.
+pub const DEFAULT_PATH: &'static str = "hypest.toml";
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub pool_size: u32,
+    pub ssl_mode: String,
+    pub auto_migrate: bool, // apply pending migrations at startup
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_address: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetsConfig {
+    pub dir: String, // served as static files
+    pub pictures_dir: String, // where the published pictures are written
+    pub originals_dir: String, // untouched uploads, with their metadata: must not be served
+    pub pictures_url: String, // URL prefix pictures_dir is served under
+    pub max_upload_size: u64, // bytes, larger picture uploads are rejected
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub lifetime: i64, // seconds since login, sent as the cookie's Max-Age
+    pub idle_timeout: i64, // seconds since the session was last used
+    pub sweep_interval: i64, // seconds between two purges of expired sessions
+    pub secure_cookie: bool, // only send the cookie over HTTPS
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordConfig {
+    pub algorithm: String, // "argon2id" or "bcrypt", for new hashes
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub bcrypt_cost: u32,
+}
+
+/// Server configuration, read from a TOML file then overridden by
+/// `HYPEST_*` environment variables.
+///
+/// ```toml
+/// [database]
+/// url = "postgresql://postgres:@127.0.0.1/hypest"  # HYPEST_DATABASE_URL
+/// pool_size = 5                                    # HYPEST_DATABASE_POOL_SIZE
+/// ssl_mode = "none"                                # HYPEST_DATABASE_SSL_MODE
+/// auto_migrate = true                              # HYPEST_DATABASE_AUTO_MIGRATE
+///
+/// [server]
+/// bind_address = "127.0.0.1:6767"                  # HYPEST_BIND_ADDRESS
+///
+/// [assets]
+/// dir = "assets"                                   # HYPEST_ASSETS_DIR
+/// pictures_dir = "assets/pictures"                 # HYPEST_PICTURES_DIR
+/// originals_dir = "originals"                      # HYPEST_ORIGINALS_DIR
+/// pictures_url = "/pictures"                       # HYPEST_PICTURES_URL
+/// max_upload_size = 10485760                       # HYPEST_MAX_UPLOAD_SIZE
+///
+/// [session]
+/// lifetime = 2592000                               # HYPEST_SESSION_LIFETIME
+/// idle_timeout = 604800                            # HYPEST_SESSION_IDLE_TIMEOUT
+/// sweep_interval = 3600                            # HYPEST_SESSION_SWEEP_INTERVAL
+/// secure_cookie = true                             # HYPEST_SESSION_SECURE_COOKIE
+///
+/// [password]
+/// algorithm = "argon2id"                           # HYPEST_PASSWORD_ALGORITHM
+/// argon2_memory_kib = 19456                        # HYPEST_PASSWORD_ARGON2_MEMORY_KIB
+/// argon2_iterations = 2                            # HYPEST_PASSWORD_ARGON2_ITERATIONS
+/// argon2_parallelism = 1                           # HYPEST_PASSWORD_ARGON2_PARALLELISM
+/// bcrypt_cost = 12                                 # HYPEST_PASSWORD_BCRYPT_COST
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub server: ServerConfig,
+    pub assets: AssetsConfig,
+    pub session: SessionConfig,
+    pub password: PasswordConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            database: DatabaseConfig {
+                url: String::from("postgresql://postgres:@127.0.0.1/hypest"),
+                pool_size: 5,
+                ssl_mode: String::from("none"),
+                auto_migrate: true,
+            },
+            server: ServerConfig {
+                bind_address: String::from("127.0.0.1:6767"),
+            },
+            assets: AssetsConfig {
+                dir: String::from("assets"),
+                pictures_dir: String::from("assets/pictures"),
+                originals_dir: String::from("originals"),
+                pictures_url: String::from("/pictures"),
+                max_upload_size: 10 * 1024 * 1024,
+            },
+            session: SessionConfig {
+                lifetime: 30 * 24 * 3600,
+                idle_timeout: 7 * 24 * 3600,
+                sweep_interval: 3600,
+                secure_cookie: true,
+            },
+            password: PasswordConfig {
+                algorithm: String::from("argon2id"),
+                argon2_memory_kib: 19456,
+                argon2_iterations: 2,
+                argon2_parallelism: 1,
+                bcrypt_cost: 12,
+            },
+        }
+    }
+}
+
+// typed access to the values of a parsed TOML document
+fn toml_str(root: &toml::Value, path: &str, target: &mut String) -> Result<(), String> {
+    match root.lookup(path) {
+        Some(value) => match value.as_str() {
+            Some(s) => { *target = String::from(s); Ok(()) },
+            None => Err(format!("`{}` must be a string", path)),
+        },
+        None => Ok(()),
+    }
+}
+
+fn toml_int<T: FromStr>(root: &toml::Value, path: &str, target: &mut T) -> Result<(), String> {
+    match root.lookup(path) {
+        Some(value) => match value.as_integer().and_then(|i| i.to_string().parse().ok()) {
+            Some(i) => { *target = i; Ok(()) },
+            None => Err(format!("`{}` must be an integer in range", path)),
+        },
+        None => Ok(()),
+    }
+}
+
+fn toml_bool(root: &toml::Value, path: &str, target: &mut bool) -> Result<(), String> {
+    match root.lookup(path) {
+        Some(value) => match value.as_bool() {
+            Some(b) => { *target = b; Ok(()) },
+            None => Err(format!("`{}` must be a boolean", path)),
+        },
+        None => Ok(()),
+    }
+}
+
+// overrides `target` with the environment variable `var` if it is set
+fn env_override<T: FromStr>(var: &str, target: &mut T) -> Result<(), String> {
+    match env::var(var) {
+        Ok(value) => match value.parse() {
+            Ok(parsed) => { *target = parsed; Ok(()) },
+            Err(_) => Err(format!("invalid value for {}: {}", var, value)),
+        },
+        Err(_) => Ok(()),
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path` (or the default location),
+    /// applies the environment overrides and validates the result.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        let explicit_path = path.map(String::from).or(env::var("HYPEST_CONFIG").ok());
+        match explicit_path {
+            Some(ref path) => try!(config.merge_file(path)),
+            None => {
+                if File::open(DEFAULT_PATH).is_ok() {
+                    try!(config.merge_file(DEFAULT_PATH));
+                }
+            }
+        }
+
+        try!(config.merge_env());
+        try!(config.validate());
+
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, path: &str) -> Result<(), String> {
+        let mut contents = String::new();
+        try!(File::open(path)
+                  .and_then(|mut f| f.read_to_string(&mut contents))
+                  .map_err(|e| format!("can't read {}: {}", path, e)));
+
+        let mut parser = toml::Parser::new(&contents);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let errors: Vec<String> = parser.errors.iter().map(|e| e.desc.clone()).collect();
+                return Err(format!("can't parse {}: {}", path, errors.join(", ")));
+            }
+        };
+        let root = toml::Value::Table(table);
+
+        try!(toml_str(&root, "database.url", &mut self.database.url));
+        try!(toml_int(&root, "database.pool_size", &mut self.database.pool_size));
+        try!(toml_str(&root, "database.ssl_mode", &mut self.database.ssl_mode));
+        try!(toml_bool(&root, "database.auto_migrate", &mut self.database.auto_migrate));
+        try!(toml_str(&root, "server.bind_address", &mut self.server.bind_address));
+        try!(toml_str(&root, "assets.dir", &mut self.assets.dir));
+        try!(toml_str(&root, "assets.pictures_dir", &mut self.assets.pictures_dir));
+        try!(toml_str(&root, "assets.originals_dir", &mut self.assets.originals_dir));
+        try!(toml_str(&root, "assets.pictures_url", &mut self.assets.pictures_url));
+        try!(toml_int(&root, "assets.max_upload_size", &mut self.assets.max_upload_size));
+        try!(toml_int(&root, "session.lifetime", &mut self.session.lifetime));
+        try!(toml_int(&root, "session.idle_timeout", &mut self.session.idle_timeout));
+        try!(toml_int(&root, "session.sweep_interval", &mut self.session.sweep_interval));
+        try!(toml_str(&root, "password.algorithm", &mut self.password.algorithm));
+        try!(toml_int(&root, "password.argon2_memory_kib", &mut self.password.argon2_memory_kib));
+        try!(toml_int(&root, "password.argon2_iterations", &mut self.password.argon2_iterations));
+        try!(toml_int(&root, "password.argon2_parallelism", &mut self.password.argon2_parallelism));
+        try!(toml_int(&root, "password.bcrypt_cost", &mut self.password.bcrypt_cost));
+        try!(toml_bool(&root, "session.secure_cookie", &mut self.session.secure_cookie));
+
+        Ok(())
+    }
+
+    fn merge_env(&mut self) -> Result<(), String> {
+        try!(env_override("HYPEST_DATABASE_URL", &mut self.database.url));
+        try!(env_override("HYPEST_DATABASE_POOL_SIZE", &mut self.database.pool_size));
+        try!(env_override("HYPEST_DATABASE_SSL_MODE", &mut self.database.ssl_mode));
+        try!(env_override("HYPEST_DATABASE_AUTO_MIGRATE", &mut self.database.auto_migrate));
+        try!(env_override("HYPEST_BIND_ADDRESS", &mut self.server.bind_address));
+        try!(env_override("HYPEST_ASSETS_DIR", &mut self.assets.dir));
+        try!(env_override("HYPEST_PICTURES_DIR", &mut self.assets.pictures_dir));
+        try!(env_override("HYPEST_ORIGINALS_DIR", &mut self.assets.originals_dir));
+        try!(env_override("HYPEST_PICTURES_URL", &mut self.assets.pictures_url));
+        try!(env_override("HYPEST_MAX_UPLOAD_SIZE", &mut self.assets.max_upload_size));
+        try!(env_override("HYPEST_SESSION_LIFETIME", &mut self.session.lifetime));
+        try!(env_override("HYPEST_SESSION_IDLE_TIMEOUT", &mut self.session.idle_timeout));
+        try!(env_override("HYPEST_SESSION_SWEEP_INTERVAL", &mut self.session.sweep_interval));
+        try!(env_override("HYPEST_PASSWORD_ALGORITHM", &mut self.password.algorithm));
+        try!(env_override("HYPEST_PASSWORD_ARGON2_MEMORY_KIB", &mut self.password.argon2_memory_kib));
+        try!(env_override("HYPEST_PASSWORD_ARGON2_ITERATIONS", &mut self.password.argon2_iterations));
+        try!(env_override("HYPEST_PASSWORD_ARGON2_PARALLELISM", &mut self.password.argon2_parallelism));
+        try!(env_override("HYPEST_PASSWORD_BCRYPT_COST", &mut self.password.bcrypt_cost));
+        try!(env_override("HYPEST_SESSION_SECURE_COOKIE", &mut self.session.secure_cookie));
+
+        Ok(())
+    }
+
+    /// Checks that every value is usable, reporting all the problems at once.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if !self.database.url.starts_with("postgres://") && !self.database.url.starts_with("postgresql://") {
+            errors.push(String::from("database.url must be a postgres:// or postgresql:// URL"));
+        }
+        if self.database.pool_size == 0 || self.database.pool_size > 100 {
+            errors.push(String::from("database.pool_size must be between 1 and 100"));
+        }
+        if self.database.ssl_mode != "none" {
+            errors.push(format!("database.ssl_mode `{}` isn't supported, only `none` is", self.database.ssl_mode));
+        }
+        if SocketAddr::from_str(&self.server.bind_address).is_err() {
+            errors.push(format!("server.bind_address `{}` isn't an ip:port address", self.server.bind_address));
+        }
+        if self.assets.dir.is_empty() {
+            errors.push(String::from("assets.dir can't be empty"));
+        }
+        if self.assets.pictures_dir.is_empty() {
+            errors.push(String::from("assets.pictures_dir can't be empty"));
+        }
+        if self.assets.originals_dir.is_empty() {
+            errors.push(String::from("assets.originals_dir can't be empty"));
+        }
+        if !self.assets.pictures_url.starts_with('/') && !self.assets.pictures_url.starts_with("http") {
+            errors.push(String::from("assets.pictures_url must be an absolute path or an http(s) URL"));
+        }
+        if self.assets.max_upload_size == 0 {
+            errors.push(String::from("assets.max_upload_size must be a positive number of bytes"));
+        }
+        if self.session.lifetime <= 0 {
+            errors.push(String::from("session.lifetime must be a positive number of seconds"));
+        }
+        if self.session.idle_timeout <= 0 || self.session.idle_timeout > self.session.lifetime {
+            errors.push(String::from("session.idle_timeout must be positive and at most session.lifetime"));
+        }
+        if self.session.sweep_interval <= 0 {
+            errors.push(String::from("session.sweep_interval must be a positive number of seconds"));
+        }
+        if self.password.algorithm != "argon2id" && self.password.algorithm != "bcrypt" {
+            errors.push(format!("password.algorithm `{}` isn't one of argon2id, bcrypt", self.password.algorithm));
+        }
+        if self.password.argon2_parallelism == 0 || self.password.argon2_iterations == 0 {
+            errors.push(String::from("password.argon2_parallelism and password.argon2_iterations must be at least 1"));
+        }
+        if self.password.argon2_memory_kib < 8 * self.password.argon2_parallelism {
+            errors.push(String::from("password.argon2_memory_kib must be at least 8 times password.argon2_parallelism"));
+        }
+        if self.password.bcrypt_cost < 4 || self.password.bcrypt_cost > 31 {
+            errors.push(String::from("password.bcrypt_cost must be between 4 and 31"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Creates the directories pictures are stored in, then checks that the
+    /// originals aren't served: that can only be told once the paths exist
+    /// and every `.`, `..` and symlink in them is resolved.
+    pub fn create_dirs(&self) -> Result<(), String> {
+        for dir in [&self.assets.dir, &self.assets.pictures_dir, &self.assets.originals_dir].iter() {
+            try!(fs::create_dir_all(dir).map_err(|e| format!("can't create {}: {}", dir, e)));
+        }
+
+        let canonical = |dir: &str| fs::canonicalize(dir).map_err(|e| format!("can't resolve {}: {}", dir, e));
+        let public_dir = try!(canonical(&self.assets.dir));
+        let originals_dir = try!(canonical(&self.assets.originals_dir));
+        if originals_dir.starts_with(&public_dir) {
+            return Err(String::from("assets.originals_dir can't be inside assets.dir, which is public"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes the configuration available to handlers, see `ConfigRequestExtensions`.
+pub struct ConfigMiddleware {
+    config: Arc<Config>,
+}
+
+impl ConfigMiddleware {
+    pub fn new(config: Arc<Config>) -> ConfigMiddleware {
+        ConfigMiddleware { config: config }
+    }
+}
+
+impl Key for ConfigMiddleware { type Value = Arc<Config>; }
+
+impl<D> Middleware<D> for ConfigMiddleware {
+    fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn, D>, res: Response<'mw, D>) -> MiddlewareResult<'mw, D> {
+        req.extensions_mut().insert::<ConfigMiddleware>(self.config.clone());
+        Ok(Continue(res))
+    }
+}
+
+pub trait ConfigRequestExtensions {
+    fn config(&self) -> Arc<Config>;
+}
+
+impl<'mw, 'conn, D> ConfigRequestExtensions for Request<'mw, 'conn, D> {
+    fn config(&self) -> Arc<Config> {
+        self.extensions().get::<ConfigMiddleware>().unwrap().clone()
+    }
+}
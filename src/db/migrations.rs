@@ -0,0 +1,125 @@
+use postgres::{Connection, GenericConnection};
+use postgres::error::Error as PgError;
+
+/// A numbered schema change, with the SQL to apply and to revert it.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+// embeds migrations/<name>.up.sql and migrations/<name>.down.sql in the binary
+macro_rules! migration {
+    ($version:expr, $name:expr) => (
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../../migrations/", $name, ".down.sql")),
+        }
+    )
+}
+
+/// Every known migration, sorted by version.
+pub static MIGRATIONS: &'static [Migration] = &[
+    migration!(1, "0001_initial_schema"),
+    migration!(2, "0002_user_roles"),
+    migration!(3, "0003_session_expiry"),
+    migration!(4, "0004_phc_password_hashes"),
+    migration!(5, "0005_unique_email"),
+    migration!(6, "0006_picture_geohash"),
+    migration!(7, "0007_picture_likes"),
+    migration!(8, "0008_picture_ratings"),
+    migration!(9, "0009_picture_image_info"),
+    migration!(10, "0010_picture_exif"),
+];
+
+/// Creates the bookkeeping table if this database has never been migrated.
+fn ensure_migrations_table(conn: &Connection) -> Result<(), PgError> {
+    conn.batch_execute("CREATE TABLE IF NOT EXISTS schema_migrations (
+                            version INTEGER PRIMARY KEY,
+                            name TEXT NOT NULL,
+                            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+                        )")
+}
+
+fn is_applied<C: GenericConnection>(conn: &C, version: i32) -> Result<bool, PgError> {
+    let stmt = try!(conn.prepare("SELECT 1 FROM schema_migrations WHERE version = $1"));
+    let rows = try!(stmt.query(&[&version]));
+    Ok(rows.len() > 0)
+}
+
+/// Returns the versions recorded in `schema_migrations`, in ascending order.
+pub fn applied_versions(conn: &Connection) -> Result<Vec<i32>, PgError> {
+    try!(ensure_migrations_table(conn));
+
+    let stmt = try!(conn.prepare("SELECT version
+                                 FROM schema_migrations
+                                 ORDER BY version"));
+    let rows = try!(stmt.query(&[]));
+
+    let versions = rows.iter().map(|row| row.get("version")).collect();
+    Ok(versions)
+}
+
+/// Applies every pending migration up to `target` (or the latest one),
+/// each in its own transaction. Returns the versions that were applied.
+pub fn migrate_up(conn: &Connection, target: Option<i32>) -> Result<Vec<i32>, PgError> {
+    try!(ensure_migrations_table(conn));
+
+    let mut applied = Vec::new();
+
+    for migration in MIGRATIONS.iter() {
+        if target.map_or(false, |target| migration.version > target) {
+            break;
+        }
+
+        let trans = try!(conn.transaction());
+        // serialize concurrent migrators (e.g. several instances starting at once)
+        try!(trans.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE"));
+
+        if try!(is_applied(&trans, migration.version)) {
+            continue; // already applied, the transaction is rolled back on drop
+        }
+
+        try!(trans.batch_execute(migration.up));
+        try!(trans.execute("INSERT INTO schema_migrations (version, name)
+                            VALUES ($1, $2)", &[&migration.version, &migration.name]));
+        try!(trans.commit());
+
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+/// Reverts every applied migration newer than `target`, most recent first.
+/// Returns the versions that were reverted.
+pub fn migrate_down(conn: &Connection, target: i32) -> Result<Vec<i32>, PgError> {
+    try!(ensure_migrations_table(conn));
+
+    let mut reverted = Vec::new();
+
+    for migration in MIGRATIONS.iter().rev() {
+        if migration.version <= target {
+            break;
+        }
+
+        let trans = try!(conn.transaction());
+        try!(trans.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE"));
+
+        if !try!(is_applied(&trans, migration.version)) {
+            continue; // never applied
+        }
+
+        try!(trans.batch_execute(migration.down));
+        try!(trans.execute("DELETE FROM schema_migrations
+                            WHERE version = $1", &[&migration.version]));
+        try!(trans.commit());
+
+        reverted.push(migration.version);
+    }
+
+    Ok(reverted)
+}
@@ -1,3 +1,24 @@
+use chrono::{Datelike, NaiveDate};
+use postgres::rows::Row;
+use config::AssetsConfig;
+use imaging::derivatives;
+use imaging::format::ImageFormat;
+
+pub mod migrations;
+
+/// Format the date in the dd/mm/yyyy format.
+fn format_date(date: &NaiveDate) -> String {
+    format!("{}/{}/{}", date.day(), date.month(), date.year())
+}
+
+/// SQL expression telling if the user bound to `$username_param` liked
+/// the picture of the current `pictures` row, selected as `liked`.
+pub fn liked_sql(username_param: usize) -> String {
+    format!("EXISTS(SELECT 1 FROM picture_likes
+                    WHERE picture_likes.picture_id = pictures.id
+                    AND picture_likes.username = ${}) AS liked", username_param)
+}
+
 #[derive(Serialize, Deserialize, Debug, RustcDecodable, RustcEncodable)]
 pub struct PictureDBData {
     pub id: i32,
@@ -6,15 +27,78 @@ pub struct PictureDBData {
     pub gps_lat: f64,
     pub gps_long: f64,
     pub date_taken: String,
-    pub rating: Option<f32>, // rating is set to -1 when there's no rating.
+    pub rating: Option<f32>, // average of the users' scores, null until the first one
+    pub rating_count: i32,
     pub likes: i32, // likes as 0 value default
+    pub liked: bool, // whether the caller liked it
+    pub mime_type: Option<String>, // null until the binary is uploaded
+    pub width: Option<i32>, // in pixels
+    pub height: Option<i32>,
+    pub byte_size: Option<i64>,
+    pub location_mismatch: bool, // the EXIF location is far from the claimed one
+    pub urls: PictureUrls,
+}
+
+impl PictureDBData {
+    /// Reads a picture from a row of `SELECT *, <liked_sql> FROM pictures`.
+    pub fn from_row(row: &Row, assets: &AssetsConfig) -> PictureDBData {
+        let id = row.get("id");
+        let mime_type: Option<String> = row.get("mime_type");
+        let urls = PictureUrls::new(assets, id, mime_type.as_ref().map(|mime| &mime[..]));
+
+        PictureDBData {
+            id: id,
+            author: row.get("author"),
+            description: row.get("description"), // optional
+            gps_lat: row.get("gps_lat"),
+            gps_long: row.get("gps_long"),
+            date_taken: format_date(&row.get("date_taken")),
+            rating: row.get("rating"), // optional
+            rating_count: row.get("rating_count"),
+            likes: row.get("likes"),
+            liked: row.get("liked"),
+            mime_type: mime_type,
+            width: row.get("width"),
+            height: row.get("height"),
+            byte_size: row.get("byte_size"),
+            location_mismatch: row.get("location_mismatch"),
+            urls: urls,
+        }
+    }
+}
+
+/// Where the binaries of a picture are served.
+#[derive(Serialize, Deserialize, Debug, RustcDecodable, RustcEncodable)]
+pub struct PictureUrls {
+    pub full: String, // full size, without the upload's metadata
+    pub thumbnail: String, // square
+    pub medium: String,
+    pub display: String,
+}
+
+impl PictureUrls {
+    /// URLs of picture `id`, stored as `mime_type`
+    /// (pictures uploaded before it was recorded are JPEGs).
+    pub fn new(assets: &AssetsConfig, id: i32, mime_type: Option<&str>) -> PictureUrls {
+        let base = assets.pictures_url.trim_right_matches('/');
+        let format = mime_type.and_then(ImageFormat::from_mime_type).unwrap_or(ImageFormat::Jpeg);
+        let derivative_url = |name: &str| {
+            let derivative = derivatives::DERIVATIVES.iter().find(|d| d.name == name).unwrap();
+            format!("{}/{}", base, derivatives::file_name(id, derivative))
+        };
+
+        PictureUrls {
+            full: format!("{}/{}.{}", base, id, format.extension()),
+            thumbnail: derivative_url("thumbnail"),
+            medium: derivative_url("medium"),
+            display: derivative_url("display"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, RustcDecodable, RustcEncodable)]
 pub struct PictureMetadata {
-    pub author: String,
     pub description: String,
-    pub rating: Option<f32>,
     pub gps_lat: f64,
     pub gps_long: f64,
 }
@@ -30,3 +114,49 @@ pub struct User {
     pub email: String,
     pub password: String,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PicturePage {
+    pub items: Vec<PictureDBData>,
+    pub next_cursor: Option<String>, // pass it back as `cursor` to get the next page, null on the last one
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NearbyPicture {
+    pub picture: PictureDBData,
+    pub distance_m: f64, // great-circle distance from the searched point, in meters
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PictureCluster {
+    pub centroid_lat: f64,
+    pub centroid_long: f64,
+    pub count: i64,
+    pub top_picture_id: i32, // best picture of the cell for the requested order_by
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LikeState {
+    pub liked: bool,
+    pub likes: i32, // the picture's like counter after the change
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rating {
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RatingState {
+    pub score: f32, // the caller's score
+    pub rating: Option<f32>, // the picture's average after the change
+    pub rating_count: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PictureDetail {
+    pub picture: PictureDBData,
+    pub author_nick: String,
+    pub uploaded: bool, // the binaries' URLs are in `picture.urls`
+    pub camera_model: Option<String>, // from the EXIF, only shown to the author
+}
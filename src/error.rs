@@ -0,0 +1,184 @@
+use std::io;
+use nickel::status::StatusCode;
+use postgres::error::{Error as PgError, SqlState};
+use serde_json;
+use validation::FieldError;
+
+/// Why a caller couldn't be authenticated.
+#[derive(Debug)]
+pub enum AuthError {
+    EmailIncorrect,
+    PasswordIncorrect,
+    SessionMissing,
+    SessionInvalid,
+}
+
+/// Why an authenticated caller isn't allowed to do something.
+#[derive(Debug)]
+pub enum AccessError {
+    AdminRequired,
+    NotAccountOwner,
+    NotPictureAuthor,
+}
+
+/// Which unique value is already in use.
+#[derive(Debug)]
+pub enum ConflictError {
+    UsernameTaken,
+    EmailTaken,
+}
+
+/// Every failure a handler can report to the client.
+#[derive(Debug)]
+pub enum ApiError {
+    Validation(String), // malformed JSON, missing or invalid parameters
+    InvalidFields(Vec<FieldError>), // well-formed payload breaking validation rules
+    NotFound(String),
+    Conflict(ConflictError),
+    Auth(AuthError),
+    Forbidden(AccessError),
+    PayloadTooLarge(u64), // the body is over this many bytes
+    UnsupportedMediaType(String), // the body isn't an image format we take
+    Storage(String), // database or filesystem failure, details are only logged
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug)]
+struct InvalidFieldsBody {
+    code: String,
+    message: String,
+    fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    /// The HTTP status sent along with the error.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ApiError::Validation(_) => StatusCode::BadRequest,
+            ApiError::InvalidFields(_) => StatusCode::BadRequest,
+            ApiError::NotFound(_) => StatusCode::NotFound,
+            ApiError::Conflict(_) => StatusCode::Conflict,
+            ApiError::Auth(_) => StatusCode::Unauthorized,
+            ApiError::Forbidden(_) => StatusCode::Forbidden,
+            ApiError::PayloadTooLarge(_) => StatusCode::PayloadTooLarge,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UnsupportedMediaType,
+            ApiError::Storage(_) => StatusCode::InternalServerError,
+        }
+    }
+
+    /// Stable, machine-readable error code for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ApiError::Validation(_) => "ValidationError",
+            ApiError::InvalidFields(_) => "ValidationError",
+            ApiError::NotFound(_) => "NotFound",
+            ApiError::Conflict(ConflictError::UsernameTaken) => "UsernameTaken",
+            ApiError::Conflict(ConflictError::EmailTaken) => "EmailTaken",
+            ApiError::Auth(AuthError::EmailIncorrect) => "EmailIncorrect",
+            ApiError::Auth(AuthError::PasswordIncorrect) => "PasswordIncorrect",
+            ApiError::Auth(AuthError::SessionMissing) => "SessionMissing",
+            ApiError::Auth(AuthError::SessionInvalid) => "SessionInvalid",
+            ApiError::Forbidden(AccessError::AdminRequired) => "AdminRequired",
+            ApiError::Forbidden(AccessError::NotAccountOwner) => "NotAccountOwner",
+            ApiError::Forbidden(AccessError::NotPictureAuthor) => "NotPictureAuthor",
+            ApiError::PayloadTooLarge(_) => "PayloadTooLarge",
+            ApiError::UnsupportedMediaType(_) => "UnsupportedMediaType",
+            ApiError::Storage(_) => "StorageError",
+        }
+    }
+
+    /// Human-readable description, safe to show to the client.
+    pub fn message(&self) -> String {
+        match *self {
+            ApiError::Validation(ref msg) => msg.clone(),
+            ApiError::InvalidFields(ref fields) => {
+                let names: Vec<&str> = fields.iter().map(|f| &f.field[..]).collect();
+                format!("invalid fields: {}", names.join(", "))
+            },
+            ApiError::NotFound(ref msg) => msg.clone(),
+            ApiError::Conflict(ConflictError::UsernameTaken) => String::from("username already taken"),
+            ApiError::Conflict(ConflictError::EmailTaken) => String::from("email already used by another account"),
+            ApiError::Auth(AuthError::EmailIncorrect) => String::from("no account with this email"),
+            ApiError::Auth(AuthError::PasswordIncorrect) => String::from("incorrect password"),
+            ApiError::Auth(AuthError::SessionMissing) => String::from("no session cookie"),
+            ApiError::Auth(AuthError::SessionInvalid) => String::from("invalid or expired session"),
+            ApiError::Forbidden(AccessError::AdminRequired) => String::from("administrator access required"),
+            ApiError::Forbidden(AccessError::NotAccountOwner) => String::from("only the account's owner can do this"),
+            ApiError::Forbidden(AccessError::NotPictureAuthor) => String::from("only the picture's author can do this"),
+            ApiError::PayloadTooLarge(max) => format!("the body is larger than the {} bytes allowed", max),
+            ApiError::UnsupportedMediaType(ref msg) => msg.clone(),
+            ApiError::Storage(_) => String::from("internal storage error"),
+        }
+    }
+
+    /// The JSON body: `{"code": "...", "message": "..."}`, plus a
+    /// `fields` list of `{"field": "...", "message": "..."}` for invalid fields.
+    pub fn to_json(&self) -> String {
+        if let ApiError::InvalidFields(ref fields) = *self {
+            let body = InvalidFieldsBody {
+                code: String::from(self.code()),
+                message: self.message(),
+                fields: fields.clone(),
+            };
+            return serde_json::ser::to_string(&body).unwrap();
+        }
+
+        let body = ErrorBody {
+            code: String::from(self.code()),
+            message: self.message(),
+        };
+
+        serde_json::ser::to_string(&body).unwrap()
+    }
+}
+
+impl From<PgError> for ApiError {
+    fn from(e: PgError) -> ApiError {
+        ApiError::Storage(e.to_string())
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> ApiError {
+        ApiError::Storage(e.to_string())
+    }
+}
+
+impl From<serde_json::error::Error> for ApiError {
+    fn from(e: serde_json::error::Error) -> ApiError {
+        ApiError::Validation(format!("malformed JSON: {}", e))
+    }
+}
+
+/// Name of the unique constraint a database error was raised by, if any.
+pub fn violated_unique_constraint(e: &PgError) -> Option<&str> {
+    match *e {
+        PgError::Db(ref db_error) => match *db_error.code() {
+            SqlState::UniqueViolation => db_error.constraint(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl ApiError {
+    /// Maps the violation of the `users` unique constraints to the
+    /// matching conflict, any other error is a storage error.
+    pub fn from_users_write(e: PgError) -> ApiError {
+        let conflict = match violated_unique_constraint(&e) {
+            Some("users_username_key") => Some(ConflictError::UsernameTaken),
+            Some("users_email_key") => Some(ConflictError::EmailTaken),
+            _ => None,
+        };
+
+        match conflict {
+            Some(conflict) => ApiError::Conflict(conflict),
+            None => ApiError::from(e),
+        }
+    }
+}
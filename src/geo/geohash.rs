@@ -0,0 +1,226 @@
+//! Geohash encoding and decoding.
+//!
+//! A geohash interleaves the bits of the longitude and the latitude (longitude
+//! first) and writes them in base 32, so that every prefix of a hash is a cell
+//! containing it. Range queries on locations become prefix queries on a text
+//! column, which a plain btree index can serve.
+
+use super::BoundingBox;
+
+const BASE32: &'static [u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longest geohash handled, about 3.7cm x 1.9cm cells.
+pub const MAX_PRECISION: usize = 12;
+
+/// Precision stored in `pictures.geohash`, about 1.2m x 0.6m cells.
+pub const STORED_PRECISION: usize = 9;
+
+fn base32_value(c: char) -> Option<u8> {
+    BASE32.iter().position(|&b| b as char == c).map(|i| i as u8)
+}
+
+/// Geohash of `precision` characters of a point.
+pub fn encode(lat: f64, long: f64, precision: usize) -> String {
+    let precision = if precision > MAX_PRECISION { MAX_PRECISION } else { precision };
+
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let (mut long_min, mut long_max) = (-180.0, 180.0);
+
+    let mut hash = String::with_capacity(precision);
+    let mut is_long_bit = true;
+    let mut bits = 0;
+    let mut value = 0;
+
+    while hash.len() < precision {
+        let bit = if is_long_bit {
+            let mid = (long_min + long_max) / 2.0;
+            if long >= mid { long_min = mid; 1 } else { long_max = mid; 0 }
+        } else {
+            let mid = (lat_min + lat_max) / 2.0;
+            if lat >= mid { lat_min = mid; 1 } else { lat_max = mid; 0 }
+        };
+
+        value = (value << 1) | bit;
+        bits += 1;
+        is_long_bit = !is_long_bit;
+
+        if bits == 5 {
+            hash.push(BASE32[value] as char);
+            bits = 0;
+            value = 0;
+        }
+    }
+
+    hash
+}
+
+/// Cell covered by a geohash, `None` if it isn't a valid one.
+pub fn decode(hash: &str) -> Option<BoundingBox> {
+    if hash.is_empty() || hash.len() > MAX_PRECISION {
+        return None;
+    }
+
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let (mut long_min, mut long_max) = (-180.0, 180.0);
+    let mut is_long_bit = true;
+
+    for c in hash.chars() {
+        let value = match base32_value(c) {
+            Some(value) => value,
+            None => return None,
+        };
+
+        for shift in (0..5).rev() {
+            let bit = (value >> shift) & 1;
+            if is_long_bit {
+                let mid = (long_min + long_max) / 2.0;
+                if bit == 1 { long_min = mid } else { long_max = mid }
+            } else {
+                let mid = (lat_min + lat_max) / 2.0;
+                if bit == 1 { lat_min = mid } else { lat_max = mid }
+            }
+            is_long_bit = !is_long_bit;
+        }
+    }
+
+    Some(BoundingBox {
+        north: lat_max,
+        south: lat_min,
+        west: long_min,
+        east: long_max,
+    })
+}
+
+/// Width and height in degrees of the cells of a given precision.
+pub fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let long_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+
+    (360.0 / 2f64.powi(long_bits), 180.0 / 2f64.powi(lat_bits))
+}
+
+// cells of `precision` characters overlapping the box
+fn cells(bbox: &BoundingBox, precision: usize) -> Vec<String> {
+    let (width, height) = cell_size(precision);
+    let max_row = (180.0 / height) as i64 - 1;
+    let max_col = (360.0 / width) as i64 - 1;
+
+    let clamp = |i: i64, max: i64| if i < 0 { 0 } else if i > max { max } else { i };
+    let first_row = clamp(((bbox.south + 90.0) / height).floor() as i64, max_row);
+    let last_row = clamp(((bbox.north + 90.0) / height).floor() as i64, max_row);
+
+    let mut hashes = Vec::new();
+
+    for (west, east) in bbox.longitude_ranges() {
+        let first_col = clamp(((west + 180.0) / width).floor() as i64, max_col);
+        let last_col = clamp(((east + 180.0) / width).floor() as i64, max_col);
+
+        for row in first_row..last_row + 1 {
+            for col in first_col..last_col + 1 {
+                // encode the center of the cell
+                let lat = -90.0 + (row as f64 + 0.5) * height;
+                let long = -180.0 + (col as f64 + 0.5) * width;
+                let hash = encode(lat, long, precision);
+                if !hashes.contains(&hash) {
+                    hashes.push(hash);
+                }
+            }
+        }
+    }
+
+    hashes
+}
+
+/// Geohash prefixes whose cells cover the box: the longest ones that take
+/// at most `max_cells` cells, and no longer than the stored geohashes.
+/// Every point of the box has a geohash starting with one of them.
+pub fn covering(bbox: &BoundingBox, max_cells: usize) -> Vec<String> {
+    let mut best = cells(bbox, 1);
+
+    // longer prefixes would match no stored geohash
+    for precision in 2..STORED_PRECISION + 1 {
+        let (width, height) = cell_size(precision);
+        let long_span = bbox.longitude_ranges().iter().fold(0.0, |acc, &(west, east)| acc + east - west);
+        // rough count first, to avoid enumerating millions of cells
+        let estimate = ((long_span / width).floor() + 2.0) * ((bbox.north - bbox.south) / height + 2.0).floor();
+        if estimate > (4 * max_cells) as f64 {
+            break;
+        }
+
+        let candidate = cells(bbox, precision);
+        if candidate.len() > max_cells {
+            break;
+        }
+        best = candidate;
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, decode, covering, cell_size, STORED_PRECISION};
+    use geo::BoundingBox;
+
+    #[test]
+    fn encodes_known_points() {
+        assert_eq!(encode(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(encode(42.6, -5.6, 5), "ezs42");
+        assert_eq!(encode(-25.382708, -49.265506, 8), "6gkzwgjz");
+    }
+
+    #[test]
+    fn decoded_cell_contains_encoded_point() {
+        let bbox = decode("u4pruydqqvj").unwrap();
+        assert!(bbox.south <= 57.64911 && 57.64911 <= bbox.north);
+        assert!(bbox.west <= 10.40744 && 10.40744 <= bbox.east);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_hashes() {
+        assert!(decode("").is_none());
+        assert!(decode("u4a").is_none()); // 'a' isn't in the alphabet
+        assert!(decode("0123456789bcd").is_none()); // too long
+    }
+
+    #[test]
+    fn cell_sizes_alternate_between_long_and_lat_bits() {
+        assert_eq!(cell_size(1), (45.0, 45.0));
+        assert_eq!(cell_size(2), (11.25, 5.625));
+    }
+
+    #[test]
+    fn covering_contains_every_corner() {
+        let bbox = BoundingBox { north: 48.9, south: 48.8, west: 2.2, east: 2.4 };
+        let prefixes = covering(&bbox, 16);
+        assert!(prefixes.len() <= 16);
+
+        for &(lat, long) in [(48.9, 2.2), (48.9, 2.4), (48.8, 2.2), (48.8, 2.4), (48.85, 2.3)].iter() {
+            let hash = encode(lat, long, 12);
+            assert!(prefixes.iter().any(|prefix| hash.starts_with(&prefix[..])), "{} isn't covered", hash);
+        }
+    }
+
+    #[test]
+    fn covering_is_no_longer_than_stored_geohashes() {
+        let bbox = BoundingBox { north: 48.85001, south: 48.85, west: 2.3, east: 2.30001 }; // about 1 m
+        let prefixes = covering(&bbox, 16);
+        assert!(!prefixes.is_empty());
+        assert!(prefixes.iter().all(|prefix| prefix.len() <= STORED_PRECISION));
+
+        let stored = encode(48.850005, 2.300005, STORED_PRECISION);
+        assert!(prefixes.iter().any(|prefix| stored.starts_with(&prefix[..])), "{} isn't covered", stored);
+    }
+
+    #[test]
+    fn covering_handles_antimeridian() {
+        let bbox = BoundingBox { north: 1.0, south: -1.0, west: 179.0, east: -179.0 };
+        let prefixes = covering(&bbox, 16);
+
+        for &(lat, long) in [(0.0, 179.5), (0.0, -179.5)].iter() {
+            let hash = encode(lat, long, 12);
+            assert!(prefixes.iter().any(|prefix| hash.starts_with(&prefix[..])), "{} isn't covered", hash);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Geographic helpers shared by the map endpoints.
+
+use std::f64::consts::PI;
+use error::ApiError;
+use validation::Validator;
+
+pub mod geohash;
+
+/// Most geohash prefixes a query is narrowed down with.
+pub const MAX_GEOHASH_PREFIXES: usize = 16;
+
+/// Mean earth radius, in meters.
+pub const EARTH_RADIUS_M: f64 = 6371008.8;
+
+fn to_radians(degrees: f64) -> f64 {
+    degrees * PI / 180.0
+}
+
+fn to_degrees(radians: f64) -> f64 {
+    radians * 180.0 / PI
+}
+
+// brings a longitude back into [-180, 180]
+fn wrap_longitude(long: f64) -> f64 {
+    if long > 180.0 {
+        long - 360.0
+    } else if long < -180.0 {
+        long + 360.0
+    } else {
+        long
+    }
+}
+
+/// Great-circle distance between two points, in meters (haversine formula).
+pub fn distance_m(lat1: f64, long1: f64, lat2: f64, long2: f64) -> f64 {
+    let d_lat = to_radians(lat2 - lat1);
+    let d_long = to_radians(long2 - long1);
+
+    let a = (d_lat / 2.0).sin().powi(2)
+          + to_radians(lat1).cos() * to_radians(lat2).cos() * (d_long / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+}
+
+/// SQL expression computing the same distance as `distance_m`, between the
+/// `lat_column`/`long_column` point and the `$lat_param`/`$long_param` one.
+pub fn distance_sql(lat_column: &str, long_column: &str, lat_param: usize, long_param: usize) -> String {
+    format!("(2 * {radius} * ASIN(LEAST(1, SQRT(\
+                POWER(SIN(RADIANS({lat} - ${lat_param}) / 2), 2) \
+                + COS(RADIANS(${lat_param})) * COS(RADIANS({lat})) \
+                * POWER(SIN(RADIANS({long} - ${long_param}) / 2), 2)))))",
+            radius = EARTH_RADIUS_M,
+            lat = lat_column,
+            long = long_column,
+            lat_param = lat_param,
+            long_param = long_param)
+}
+
+/// Area seen by the client, from its top-left and bottom-right corners.
+///
+/// `west` is greater than `east` when the box crosses the antimeridian:
+/// tl_long=170, br_long=-170 is the 20° window around 180°, not the
+/// 340° band on the other side of the globe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub north: f64,
+    pub south: f64,
+    pub west: f64,
+    pub east: f64,
+}
+
+impl BoundingBox {
+    /// Builds the box from the viewport's corners, rejecting out of range
+    /// coordinates and a top edge below the bottom one.
+    pub fn from_corners(tl_lat: f64, tl_long: f64, br_lat: f64, br_long: f64) -> Result<BoundingBox, ApiError> {
+        try!(Validator::new()
+                 .latitude("tl_lat", tl_lat)
+                 .longitude("tl_long", tl_long)
+                 .latitude("br_lat", br_lat)
+                 .longitude("br_long", br_long)
+                 .finish());
+
+        if tl_lat < br_lat {
+            return Err(ApiError::Validation(String::from("inverted latitudes: `tl_lat` is below `br_lat`")));
+        }
+
+        Ok(BoundingBox {
+            north: tl_lat,
+            south: br_lat,
+            west: tl_long,
+            east: br_long,
+        })
+    }
+
+    /// Smallest box containing the circle of `radius_m` meters around a point,
+    /// used to pre-filter radius searches.
+    pub fn around(lat: f64, long: f64, radius_m: f64) -> BoundingBox {
+        let angular_radius = radius_m / EARTH_RADIUS_M;
+        let d_lat = to_degrees(angular_radius);
+
+        let north = lat + d_lat;
+        let south = lat - d_lat;
+
+        if north >= 90.0 || south <= -90.0 {
+            // the circle contains a pole: every longitude is in
+            return BoundingBox {
+                north: north.min(90.0),
+                south: south.max(-90.0),
+                west: -180.0,
+                east: 180.0,
+            };
+        }
+
+        let sin_d_long = angular_radius.sin() / to_radians(lat).cos();
+        if sin_d_long >= 1.0 {
+            return BoundingBox { north: north, south: south, west: -180.0, east: 180.0 };
+        }
+
+        let d_long = to_degrees(sin_d_long.asin());
+        BoundingBox {
+            north: north,
+            south: south,
+            west: wrap_longitude(long - d_long),
+            east: wrap_longitude(long + d_long),
+        }
+    }
+
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.west > self.east
+    }
+
+    /// Longitude intervals covered by the box, west to east:
+    /// one, or two when it crosses the antimeridian.
+    pub fn longitude_ranges(&self) -> Vec<(f64, f64)> {
+        if self.crosses_antimeridian() {
+            vec![(self.west, 180.0), (-180.0, self.east)]
+        } else {
+            vec![(self.west, self.east)]
+        }
+    }
+
+    /// SQL condition matching the points of `lat_column`/`long_column` inside
+    /// the box. Placeholders are numbered from `first_param`; the values to
+    /// bind to them are returned in order.
+    pub fn sql_condition(&self, lat_column: &str, long_column: &str, first_param: usize) -> (String, Vec<f64>) {
+        let mut values = vec![self.south, self.north];
+        let mut long_conditions = Vec::new();
+
+        for (west, east) in self.longitude_ranges() {
+            let param = first_param + values.len();
+            long_conditions.push(format!("{} BETWEEN ${} AND ${}", long_column, param, param + 1));
+            values.push(west);
+            values.push(east);
+        }
+
+        let condition = format!("{} BETWEEN ${} AND ${} AND ({})",
+                                lat_column, first_param, first_param + 1,
+                                long_conditions.join(" OR "));
+        (condition, values)
+    }
+
+    /// SQL condition matching the rows of `geohash_column` in the cells
+    /// covering the box, which the geohash index can serve. It may match
+    /// points just outside the box, so it goes along with `sql_condition`.
+    pub fn geohash_condition(&self, geohash_column: &str, first_param: usize) -> (String, Vec<String>) {
+        let prefixes = geohash::covering(self, MAX_GEOHASH_PREFIXES);
+        let conditions: Vec<String> = (0..prefixes.len())
+            .map(|i| format!("{} LIKE ${}", geohash_column, first_param + i))
+            .collect();
+        let patterns = prefixes.iter().map(|prefix| format!("{}%", prefix)).collect();
+
+        (format!("({})", conditions.join(" OR ")), patterns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoundingBox, distance_m};
+
+    #[test]
+    fn regular_box_has_one_range() {
+        let bbox = BoundingBox::from_corners(50.0, -5.0, 40.0, 10.0).unwrap();
+        assert!(!bbox.crosses_antimeridian());
+        assert_eq!(bbox.longitude_ranges(), vec![(-5.0, 10.0)]);
+    }
+
+    #[test]
+    fn box_crossing_antimeridian_is_split() {
+        let bbox = BoundingBox::from_corners(10.0, 170.0, -10.0, -170.0).unwrap();
+        assert!(bbox.crosses_antimeridian());
+        assert_eq!(bbox.longitude_ranges(), vec![(170.0, 180.0), (-180.0, -170.0)]);
+    }
+
+    #[test]
+    fn box_ending_on_antimeridian_is_not_split() {
+        let bbox = BoundingBox::from_corners(10.0, 170.0, -10.0, 180.0).unwrap();
+        assert_eq!(bbox.longitude_ranges(), vec![(170.0, 180.0)]);
+    }
+
+    #[test]
+    fn single_meridian_box_is_not_split() {
+        let bbox = BoundingBox::from_corners(10.0, 20.0, -10.0, 20.0).unwrap();
+        assert_eq!(bbox.longitude_ranges(), vec![(20.0, 20.0)]);
+    }
+
+    #[test]
+    fn inverted_latitude_is_rejected() {
+        assert!(BoundingBox::from_corners(-10.0, 0.0, 10.0, 20.0).is_err());
+    }
+
+    #[test]
+    fn out_of_range_coordinates_are_rejected() {
+        assert!(BoundingBox::from_corners(91.0, 0.0, 10.0, 20.0).is_err());
+        assert!(BoundingBox::from_corners(10.0, -181.0, 0.0, 20.0).is_err());
+        assert!(BoundingBox::from_corners(10.0, 0.0, 0.0, ::std::f64::NAN).is_err());
+    }
+
+    #[test]
+    fn sql_condition_numbers_params_from_first_param() {
+        let bbox = BoundingBox::from_corners(10.0, 170.0, -10.0, -170.0).unwrap();
+        let (condition, values) = bbox.sql_condition("gps_lat", "gps_long", 3);
+        assert_eq!(condition, "gps_lat BETWEEN $3 AND $4 AND \
+                               (gps_long BETWEEN $5 AND $6 OR gps_long BETWEEN $7 AND $8)");
+        assert_eq!(values, vec![-10.0, 10.0, 170.0, 180.0, -180.0, -170.0]);
+    }
+
+    #[test]
+    fn geohash_condition_matches_prefixes() {
+        let bbox = BoundingBox::from_corners(48.9, 2.2, 48.8, 2.4).unwrap();
+        let (condition, patterns) = bbox.geohash_condition("geohash", 7);
+        assert!(condition.starts_with("(geohash LIKE $7"));
+        assert_eq!(condition.matches("LIKE").count(), patterns.len());
+        assert!(patterns.iter().all(|pattern| pattern.ends_with('%')));
+    }
+
+    #[test]
+    fn distance_between_known_points() {
+        // Paris to London is about 344km
+        let d = distance_m(48.8566, 2.3522, 51.5074, -0.1278);
+        assert!((d - 343_900.0).abs() < 1_000.0);
+        assert_eq!(distance_m(10.0, 20.0, 10.0, 20.0), 0.0);
+    }
+
+    #[test]
+    fn box_around_point_contains_circle() {
+        let bbox = BoundingBox::around(45.0, 5.0, 10_000.0);
+        assert!(bbox.north > 45.08 && bbox.south < 44.92);
+        assert!(bbox.west < 4.88 && bbox.east > 5.12);
+        assert!(!bbox.crosses_antimeridian());
+    }
+
+    #[test]
+    fn box_around_point_near_antimeridian_wraps() {
+        let bbox = BoundingBox::around(0.0, 179.99, 10_000.0);
+        assert!(bbox.crosses_antimeridian());
+    }
+
+    #[test]
+    fn box_around_pole_covers_all_longitudes() {
+        let bbox = BoundingBox::around(89.99, 0.0, 10_000.0);
+        assert_eq!((bbox.west, bbox.east, bbox.north), (-180.0, 180.0, 90.0));
+    }
+}
@@ -0,0 +1,38 @@
+use super::prelude::*;
+use r2d2::PooledConnection;
+use r2d2_postgres::PostgresConnectionManager;
+
+/// Checks that `user` may update or delete the account `username`
+pub fn check_account_owner(user: &CurrentUser, username: &str) -> Result<(), ApiError> {
+    /*
+        only the account's owner, or an admin
+    */
+    if user.is_admin || user.username == username {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(AccessError::NotAccountOwner))
+    }
+}
+
+/// Checks that `user` may upload the binary of picture `pic_id`
+pub fn check_picture_author(conn: &PooledConnection<PostgresConnectionManager>, user: &CurrentUser, pic_id: i32) -> Result<(), ApiError> {
+    /*
+        only the user who created the picture's metadata, or an admin.
+        fails with NotFound if the picture doesn't exist
+    */
+    let stmt = try!(conn.prepare("SELECT author
+                            FROM pictures
+                            WHERE id = $1"));
+    let rows = try!(stmt.query(&[&pic_id]));
+
+    if rows.len() == 0 {
+        return Err(ApiError::NotFound(format!("no picture with id {}", pic_id)));
+    }
+
+    let author: String = rows.get(0).get("author");
+    if user.is_admin || user.username == author {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(AccessError::NotPictureAuthor))
+    }
+}
@@ -0,0 +1,76 @@
+use super::prelude::*;
+use super::utils;
+use postgres::Transaction;
+
+fn set_like(trans: &Transaction, username: &String, pic_id: i32, liked: bool) -> Result<db::LikeState, ApiError> {
+    /*
+        adds or removes the user's like, and moves the picture's counter
+        only when that changed something, so repeating a call is harmless
+    */
+
+    // lock the picture: concurrent likes of it wait for this transaction
+    let stmt = try!(trans.prepare("SELECT likes
+                            FROM pictures
+                            WHERE id = $1
+                            AND uploaded = TRUE
+                            FOR UPDATE"));
+    let rows = try!(stmt.query(&[&pic_id]));
+
+    if rows.len() == 0 {
+        return Err(ApiError::NotFound(format!("no picture with id {}", pic_id)));
+    }
+    let likes: i32 = rows.get(0).get("likes");
+
+    let stmt = if liked {
+        try!(trans.prepare("INSERT INTO picture_likes (username, picture_id)
+                      SELECT $1, $2
+                      WHERE NOT EXISTS (SELECT 1 FROM picture_likes
+                                        WHERE username = $1 AND picture_id = $2)"))
+    } else {
+        try!(trans.prepare("DELETE FROM picture_likes
+                      WHERE username = $1 AND picture_id = $2"))
+    };
+    let changed = try!(stmt.execute(&[username, &pic_id])) > 0;
+
+    if !changed {
+        return Ok(db::LikeState { liked: liked, likes: likes });
+    }
+
+    let delta: i32 = if liked { 1 } else { -1 };
+    let stmt = try!(trans.prepare("UPDATE pictures
+                            SET likes = likes + $1
+                            WHERE id = $2"));
+    try!(stmt.execute(&[&delta, &pic_id]));
+
+    Ok(db::LikeState { liked: liked, likes: likes + delta })
+}
+
+fn respond_with_like(req: &mut Request, liked: bool) -> Result<String, ApiError> {
+    let conn = req.db_conn();
+    let username = try!(req.current_user()).username;
+    let pic_id: i32 = try!(utils::parse_param("id", req.param("id")));
+
+    let trans = try!(conn.transaction());
+    let state = try!(set_like(&trans, &username, pic_id, liked));
+    try!(trans.commit());
+
+    Ok(serde_json::ser::to_string(&state).unwrap())
+}
+
+pub fn post(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        like a picture, does nothing if the caller already likes it
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+
+    respond_with_like(req, true)
+}
+
+pub fn delete(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        remove the caller's like, does nothing if there is none
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+
+    respond_with_like(req, false)
+}
@@ -1,5 +1,8 @@
 use super::prelude::*;
-use super::utils;
+use super::sessions;
+use password;
+use password::Verification;
+use validation;
 
 use std::cell::RefCell;
 use rand::os::OsRng;
@@ -14,10 +17,20 @@ struct UserCredentials {
     pub password: String,
 }
 
-pub enum LoginStatus {
-    LoginOk,
-    EmailIncorrect,
-    PasswordIncorrect,
+impl Validate for UserCredentials {
+    fn validate(&self) -> Result<(), ApiError> {
+        // no email syntax or password policy here, accounts may predate them
+        Validator::new()
+            .length("email", &self.email, 1, validation::EMAIL_MAX_LENGTH)
+            .length("password", &self.password, 1, validation::PASSWORD_MAX_LENGTH)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct LoginResponse {
+    pub code: String,
+    pub token: String, // for native clients that don't keep cookies
 }
 
 
@@ -30,29 +43,28 @@ fn os_random<T: Rand>() -> T {
 }
 
 
-// TODO: use a proper status enum to represent the different failure modes
-// - missing email
-// - incorrect password
-pub fn post(req: &mut Request, res: &mut Response) -> LoginStatus {
+pub fn post(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
     /*
         login with email and password
     */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
     res.set(AccessControlAllowOrigin::Any);
 
     let conn = req.db_conn();
 
-    let credentials: UserCredentials = serde_json::de::from_reader(&mut req.origin).unwrap();
+    let credentials: UserCredentials = try!(serde_json::de::from_reader(&mut req.origin));
+    try!(credentials.validate());
 
     // test if email exists
-    let stmt = conn.prepare("SELECT username, email, password, salt
+    let stmt = try!(conn.prepare("SELECT username, email, password, salt
                             FROM users
                             WHERE email = $1
-                            LIMIT 1").unwrap();
+                            LIMIT 1"));
 
-    let rows = stmt.query(&[&credentials.email]).unwrap();
+    let rows = try!(stmt.query(&[&credentials.email]));
 
     if rows.len() == 0 {
-        return LoginStatus::EmailIncorrect; // email doesn't exists
+        return Err(ApiError::Auth(AuthError::EmailIncorrect)); // email doesn't exists
     } else {
         let row = rows.get(0); // getting the row
         let db_email: String = row.get("email");
@@ -60,19 +72,26 @@ pub fn post(req: &mut Request, res: &mut Response) -> LoginStatus {
         if db_email == credentials.email {
             // now test if password's hash is the same as db's hash
             let db_password: String = row.get("password");
-            let db_salt: Vec<u8> = row.get("salt");
-
-            // hash the password with db's salt
-            let cost = 10;
-            let mut password_hash_bin: Vec<u8> = vec![0; 24];
-
-            bcrypt(cost, &db_salt, &credentials.password.into_bytes(), &mut password_hash_bin);
-
-            let password_hash: String = utils::to_base64(&password_hash_bin);
+            let db_salt: Option<Vec<u8>> = row.get("salt"); // legacy hashes only
+            let username: String = row.get("username");
+
+            let config = req.config();
+            let verification = try!(password::verify(&config.password,
+                                                     &credentials.password,
+                                                     &db_password,
+                                                     db_salt.as_ref().map(|salt| &salt[..])));
+
+            if verification == Verification::ValidNeedsRehash {
+                // upgrade the stored hash now that we know the password
+                let password_hash = try!(password::hash(&config.password, &credentials.password));
+                let stmt = try!(conn.prepare("UPDATE users
+                                        SET password = $1, salt = NULL
+                                        WHERE username = $2"));
+                try!(stmt.execute(&[&password_hash, &username]));
+            }
 
-            if db_password == password_hash {
+            if verification != Verification::Invalid {
                 // session creation processus
-                let username: String = row.get("username");
 
                 // generate the token
                 let token: [u8; 32] = os_random();
@@ -89,20 +108,26 @@ pub fn post(req: &mut Request, res: &mut Response) -> LoginStatus {
 
                 // RETURN THIS UNHASHED TOKEN HEX IN SET-COOKIE
                 let token_hex = token.to_hex(); // serialize the token to hex
-                println!("{}", token_hex);
 
                 // create session row in database
-                let stmt = conn.prepare("INSERT INTO sessions
+                let stmt = try!(conn.prepare("INSERT INTO sessions
                                         (username, token_hash, date_created)
-                                        VALUES($1, $2, NOW())").unwrap();
-                let _query = stmt.query(&[&username, &token_hash_hex]).unwrap();
+                                        VALUES($1, $2, NOW())"));
+                try!(stmt.execute(&[&username, &token_hash_hex]));
+
+                let cookie = sessions::session_cookie(&token_hex, &config.session);
+                res.headers_mut().set_raw("Set-Cookie", vec![cookie.into_bytes()]);
 
-                return LoginStatus::LoginOk;
+                let response = LoginResponse {
+                    code: String::from("LoginOk"),
+                    token: token_hex,
+                };
+                return Ok(serde_json::ser::to_string(&response).unwrap());
             }  else {
-                return LoginStatus::PasswordIncorrect;
+                return Err(ApiError::Auth(AuthError::PasswordIncorrect));
             }
         } else {
-            return LoginStatus::EmailIncorrect;
+            return Err(ApiError::Auth(AuthError::EmailIncorrect));
         }
     }
 }
@@ -0,0 +1,42 @@
+use super::prelude::*;
+use super::sessions;
+
+pub fn post(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        logout: delete the current session
+        and remove the client's cookie
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+
+    let conn = req.db_conn();
+    let token_hash_hex = try!(sessions::current_token_hash(req));
+
+    let stmt = try!(conn.prepare("DELETE FROM sessions
+                            WHERE token_hash = $1"));
+    try!(stmt.execute(&[&token_hash_hex]));
+
+    let cookie = sessions::clear_session_cookie(&req.config().session);
+    res.headers_mut().set_raw("Set-Cookie", vec![cookie.into_bytes()]);
+
+    Ok(String::from("{\"code\":\"LogoutOk\"}"))
+}
+
+pub fn post_all(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        logout everywhere: delete every session
+        of the current user
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+
+    let conn = req.db_conn();
+    let user = try!(req.current_user());
+
+    let stmt = try!(conn.prepare("DELETE FROM sessions
+                            WHERE username = $1"));
+    try!(stmt.execute(&[&user.username]));
+
+    let cookie = sessions::clear_session_cookie(&req.config().session);
+    res.headers_mut().set_raw("Set-Cookie", vec![cookie.into_bytes()]);
+
+    Ok(String::from("{\"code\":\"LogoutOk\"}"))
+}
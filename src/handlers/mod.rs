@@ -1,8 +1,41 @@
+use std::io;
+use std::io::Write;
+use nickel::{Response, MediaType};
+use error::ApiError;
+
 mod prelude;
 mod utils;
+mod authz;
 
 pub mod pictures_in_area;
+pub mod pictures_near;
+pub mod picture_clusters;
 pub mod pictures;
+pub mod likes;
+pub mod ratings;
 pub mod users;
 pub mod login;
+pub mod logout;
 pub mod sessions;
+pub mod policy;
+
+/// Turns a handler's result into the response body
+pub fn respond(res: &mut Response, result: Result<String, ApiError>) -> String {
+    /*
+        on failure, sets the error's HTTP status
+        and replaces the body with the JSON error
+    */
+    match result {
+        Ok(body) => body,
+        Err(e) => {
+            if let ApiError::Storage(ref detail) = e {
+                // for the operator, never in the response nor on stdout
+                let _ = writeln!(io::stderr(), "storage error: {}", detail);
+            }
+
+            res.set(e.status());
+            res.set(MediaType::Json);
+            e.to_json()
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use super::prelude::*;
+use super::utils;
+use postgres::types::ToSql;
+use geo::BoundingBox;
+
+/// Cells per 256px map tile when the grid is derived from `zoom`.
+pub const CELLS_PER_TILE: f64 = 8.0;
+pub const MAX_ZOOM: i32 = 22;
+/// Most clusters returned, the most populated first.
+pub const MAX_CLUSTERS: i64 = 1000;
+
+/// Expression ranking the pictures of a cell, for each accepted `order_by`.
+fn rank_key(order_by: &str) -> Option<&'static str> {
+  match order_by {
+    "likes" => Some("likes"),
+    "rating" => Some("COALESCE(rating, -1)"),
+    "date_taken" => Some("date_taken"),
+    _ => None,
+  }
+}
+
+pub fn get(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+  /*
+      aggregate the pictures of the given area into grid cells,
+      each with its centroid, its number of pictures
+      and the id of its best picture
+  */
+
+  // HTTP headers
+  res.set(MediaType::Json); // Content-Type: application/json
+  res.set(AccessControlAllowOrigin::Any);
+
+  let conn = req.db_conn();
+  let query = req.query();
+
+  let order_by = match query.get("order_by") {
+    Some(order_by) => order_by,
+    None => return Err(ApiError::Validation(String::from("missing parameter `order_by`"))),
+  };
+  let rank_key = match rank_key(order_by) {
+    Some(rank_key) => rank_key,
+    None => return Err(ApiError::Validation(format!("invalid parameter `order_by`: {}", order_by))),
+  };
+
+  // get the border coords
+  let tl_lat: f64 = try!(utils::parse_param("tl_lat", query.get("tl_lat")));
+  let tl_long: f64 = try!(utils::parse_param("tl_long", query.get("tl_long")));
+  let br_lat: f64 = try!(utils::parse_param("br_lat", query.get("br_lat")));
+  let br_long: f64 = try!(utils::parse_param("br_long", query.get("br_long")));
+  let bbox = try!(BoundingBox::from_corners(tl_lat, tl_long, br_lat, br_long));
+
+  // cell size in degrees: given directly, or from the map's zoom level
+  let grid_size: f64 = match (query.get("grid_size"), query.get("zoom")) {
+    (Some(_), _) => try!(utils::parse_param("grid_size", query.get("grid_size"))),
+    (None, Some(_)) => {
+      let zoom: i32 = try!(utils::parse_param("zoom", query.get("zoom")));
+      try!(Validator::new().range("zoom", zoom as f64, 0.0, MAX_ZOOM as f64).finish());
+      360.0 / 2f64.powi(zoom) / CELLS_PER_TILE
+    },
+    (None, None) => return Err(ApiError::Validation(String::from("either `grid_size` or `zoom` is required"))),
+  };
+  try!(Validator::new().range("grid_size", grid_size, 0.00001, 90.0).finish());
+
+  let (in_bbox, bbox_values) = bbox.sql_condition("gps_lat", "gps_long", 3);
+  let (in_cells, cell_patterns) = bbox.geohash_condition("geohash", 3 + bbox_values.len());
+  let max_clusters = MAX_CLUSTERS;
+
+  let mut params: Vec<&ToSql> = vec![&grid_size, &max_clusters];
+  for value in bbox_values.iter() {
+    params.push(value);
+  }
+  for pattern in cell_patterns.iter() {
+    params.push(pattern);
+  }
+
+  let stmt = try!(conn.prepare(&format!("SELECT COUNT(*) AS count,
+                                  AVG(gps_lat) AS centroid_lat,
+                                  AVG(gps_long) AS centroid_long,
+                                  (ARRAY_AGG(id ORDER BY {} DESC, id DESC))[1] AS top_picture_id
+                           FROM (
+                               SELECT *,
+                                      FLOOR(gps_lat / $1) AS cell_lat,
+                                      FLOOR(gps_long / $1) AS cell_long
+                               FROM pictures
+                               WHERE {}
+                               AND {}
+                               AND uploaded=TRUE
+                           ) AS cells
+                           GROUP BY cell_lat, cell_long
+                           ORDER BY count DESC
+                           LIMIT $2", rank_key, in_cells, in_bbox)));
+
+  let mut clusters = Vec::new();
+
+  for row in try!(stmt.query(&params)) {
+      clusters.push(db::PictureCluster {
+          centroid_lat: row.get("centroid_lat"),
+          centroid_long: row.get("centroid_long"),
+          count: row.get("count"),
+          top_picture_id: row.get("top_picture_id"),
+      });
+  }
+
+  Ok(serde_json::ser::to_string(&clusters).unwrap())
+}
@@ -1,7 +1,62 @@
 use super::prelude::*;
+use super::utils;
+use super::authz;
+use geo;
+use geo::geohash;
+use hyper::header::ContentLength;
+use imaging::exif;
+use imaging::exif::Exif;
+use imaging::format;
+use imaging::format::{ImageFormat, ImageInfo};
+use imaging::publish;
+use rand;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+pub fn get(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        get the metadata of one uploaded picture,
+        with its author's nick and the URLs of its binaries
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+    res.set(AccessControlAllowOrigin::Any);
+
+    let conn = req.db_conn();
+    let user = try!(req.current_user());
+    let username = user.username.clone();
+    let pic_id: i32 = try!(utils::parse_param("id", req.param("id")));
+
+    let stmt = try!(conn.prepare(&format!("SELECT pictures.*, {}, users.nick AS author_nick
+                            FROM pictures
+                            JOIN users ON users.username = pictures.author
+                            WHERE pictures.id = $1
+                            AND pictures.uploaded = TRUE", db::liked_sql(2))));
+    let rows = try!(stmt.query(&[&pic_id, &username]));
+
+    if rows.len() == 0 {
+        // pictures still waiting for their binary aren't visible either
+        return Err(ApiError::NotFound(format!("no picture with id {}", pic_id)));
+    }
+
+    let row = rows.get(0);
+    let picture = db::PictureDBData::from_row(&row, &req.config().assets);
+    // device metadata stays private, like the original upload
+    let camera_model: Option<String> = if user.is_admin || picture.author == username { row.get("camera_model") } else { None };
+    let detail = db::PictureDetail {
+        picture: picture,
+        author_nick: row.get("author_nick"),
+        uploaded: row.get("uploaded"),
+        camera_model: camera_model,
+    };
+
+    Ok(serde_json::ser::to_string(&detail).unwrap())
+}
 
 // Accepts only JSON
-pub fn post(req: &mut Request, res: &mut Response) -> String {
+pub fn post(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
     /*
         inserting picture's metadata into the database.
         the API returns the id of the created row, and returns this id.
@@ -11,51 +66,190 @@ pub fn post(req: &mut Request, res: &mut Response) -> String {
     res.set(MediaType::Json); // HTTP header : Content-Type: application/json (for return)
 
     let conn = req.db_conn();
+    let author = try!(req.current_user()).username; // the caller is the author
     // retreive the metadata in JSON
-    let pic_metadata: db::PictureMetadata = serde_json::de::from_reader(&mut req.origin).unwrap();
+    let pic_metadata: db::PictureMetadata = try!(serde_json::de::from_reader(&mut req.origin));
+    try!(pic_metadata.validate());
 
+    // kept alongside the coordinates for the map queries' index
+    let pic_geohash = geohash::encode(pic_metadata.gps_lat, pic_metadata.gps_long, geohash::STORED_PRECISION);
 
-    let stmt = conn.prepare("INSERT INTO pictures
-                            (author, description, gps_lat, gps_long, date_taken, rating, uploaded)
-                            VALUES($1, $2, $3, $4, NOW(), $5, FALSE)
-                            RETURNING id").unwrap();
-    let rows = stmt.query(&[&pic_metadata.author,
+    let stmt = try!(conn.prepare("INSERT INTO pictures
+                            (author, description, gps_lat, gps_long, claimed_gps_lat, claimed_gps_long, geohash, date_taken, uploaded)
+                            VALUES($1, $2, $3, $4, $3, $4, $5, NOW(), FALSE)
+                            RETURNING id"));
+    let rows = try!(stmt.query(&[&author,
                             &pic_metadata.description,
                             &pic_metadata.gps_lat,
                             &pic_metadata.gps_long,
-                            &pic_metadata.rating]).unwrap();
+                            &pic_geohash]));
 
     let first_and_only_row = rows.get(0); // getting the first and only one row
     let pic_id = db::ReturnId { // creating an ID struct to convert in JSON
         id: first_and_only_row.get("id"),
     };
 
-    serde_json::ser::to_string(&pic_id).unwrap() // returning the id in json
+    Ok(serde_json::ser::to_string(&pic_id).unwrap()) // returning the id in json
+}
+
+/// Distance between the claimed and the embedded location
+/// above which a picture is flagged, in meters.
+pub const LOCATION_MISMATCH_M: f64 = 1_000.0;
+pub const CAMERA_MODEL_MAX_LENGTH: usize = 100;
+
+/// A binary received by `put`.
+struct Upload {
+    byte_size: u64,
+    info: ImageInfo,
+    exif: Option<Exif>,
+}
+
+fn receive_upload<R: Read>(body: &mut R, temp_path: &Path, max_size: u64, expected_size: Option<u64>) -> Result<Upload, ApiError> {
+    /*
+        streams the body into temp_path, never reading more than
+        max_size + 1 bytes, checks it's an image and syncs it to disk.
+        returns its size, what its header says and its EXIF
+    */
+    let mut file = try!(OpenOptions::new().read(true).write(true).create(true).truncate(true).open(temp_path));
+    let written = try!(io::copy(&mut body.take(max_size + 1), &mut file));
+
+    if written > max_size {
+        return Err(ApiError::PayloadTooLarge(max_size));
+    }
+    if written == 0 {
+        return Err(ApiError::Validation(String::from("the picture's binary is empty")));
+    }
+    if let Some(expected_size) = expected_size {
+        if written != expected_size {
+            return Err(ApiError::Validation(format!("received {} bytes, Content-Length announced {}", written, expected_size)));
+        }
+    }
+
+    // trust the content, not the client
+    try!(file.seek(SeekFrom::Start(0)));
+    let info = try!(format::detect(&mut file));
+
+    let exif = match info.format {
+        ImageFormat::Jpeg => try!(exif::read(&mut file)),
+        ImageFormat::Png => None,
+    };
+
+    try!(file.sync_all()); // durable before it replaces anything
+    Ok(Upload { byte_size: written, info: info, exif: exif })
 }
 
-pub fn put(req: &mut Request, _res: &mut Response) {
+pub fn put(req: &mut Request, _res: &mut Response) -> Result<String, ApiError> {
     /*
         assuming the iOS client has uploaded the picture,
-        this PUT request is for updating "uploaded" column to TRUE
-        and uploading the picture's binary
+        this PUT request is for uploading the picture's binary
+        and updating "uploaded" column to TRUE once it's on disk.
+        the location and date embedded in the picture replace the
+        client's ones, unless `?prefer_client=true`
     */
 
     let conn = req.db_conn();
-    let buf_size = 3*1024*1024; // 3mb buffer size
+    let config = req.config();
+    let max_size = config.assets.max_upload_size;
+
+    let pic_id: i32 = try!(utils::parse_param("id", req.param("id")));
+
+    // keep the client's location and date over the ones embedded in the picture
+    let prefer_client: bool = match req.query().get("prefer_client") {
+        Some(value) => try!(utils::parse_param("prefer_client", Some(value))),
+        None => false,
+    };
+
+    // make sure the metadata exists and belongs to the caller before writing anything
+    try!(authz::check_picture_author(&conn, &try!(req.current_user()), pic_id));
+
+    // refuse announced oversized bodies without reading them
+    let expected_size = req.origin.headers.get::<ContentLength>().map(|length| length.0);
+    if let Some(expected_size) = expected_size {
+        if expected_size > max_size {
+            return Err(ApiError::PayloadTooLarge(max_size));
+        }
+    }
+
+    let pictures_dir = Path::new(&config.assets.pictures_dir);
+    let originals_dir = Path::new(&config.assets.originals_dir);
+    // unique per request, so concurrent uploads of the same picture don't mix,
+    // and out of the served directory since it still holds the metadata
+    let temp_path = originals_dir.join(format!(".{}.{:x}.upload.tmp", pic_id, rand::random::<u64>()));
+
+    let Upload { byte_size, info, exif } = match receive_upload(&mut req.origin, &temp_path, max_size, expected_size) {
+        Ok(upload) => upload,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        },
+    };
+    let exif = exif.unwrap_or(Exif::default());
+    let original_path = originals_dir.join(format!("{}.{}", pic_id, info.format.extension()));
+
+    // serve a copy without the metadata, upright; decoding the whole
+    // picture also catches corrupt data past the header
+    let (width, height) = match publish::publish(&temp_path, info.format, exif.orientation, pictures_dir, pic_id) {
+        Ok(dimensions) => dimensions,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        },
+    };
+
+    // the rename is atomic: the kept original is the old upload or the new one, never a partial one
+    if let Err(e) = fs::rename(&temp_path, &original_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(ApiError::from(e));
+    }
+    try!(try!(File::open(originals_dir)).sync_all()); // persist the rename itself
+    try!(try!(File::open(pictures_dir)).sync_all());
+
+    // a previous upload in the other format is now stale
+    for other in [ImageFormat::Jpeg, ImageFormat::Png].iter().filter(|other| **other != info.format) {
+        let stale = format!("{}.{}", pic_id, other.extension());
+        let _ = fs::remove_file(pictures_dir.join(&stale));
+        let _ = fs::remove_file(originals_dir.join(&stale));
+    }
+
+    // cross-check the location the client claimed with the embedded one;
+    // not with gps_lat/gps_long, which a previous upload may have replaced
+    let stmt = try!(conn.prepare("SELECT claimed_gps_lat, claimed_gps_long
+                            FROM pictures
+                            WHERE id=$1"));
+    let rows = try!(stmt.query(&[&pic_id]));
+    if rows.len() == 0 {
+        // deleted during the upload, along with its author's account
+        return Err(ApiError::NotFound(format!("no picture with id {}", pic_id)));
+    }
+    let (mut gps_lat, mut gps_long): (f64, f64) = (rows.get(0).get("claimed_gps_lat"), rows.get(0).get("claimed_gps_long"));
 
-    let pic_id = req.param("id").unwrap()
-                                .parse::<i32>()
-                                .ok()
-                                .expect("invalid id");
-    let mut bytes = Vec::<u8>::with_capacity(buf_size); // 3mb buffer size
-    req.origin.read_to_end(&mut bytes).unwrap(); // read the request's body
+    let mut location_mismatch = false;
+    if let Some((exif_lat, exif_long)) = exif.gps {
+        location_mismatch = geo::distance_m(gps_lat, gps_long, exif_lat, exif_long) > LOCATION_MISMATCH_M;
+        if !prefer_client {
+            gps_lat = exif_lat;
+            gps_long = exif_long;
+        }
+    }
+    let pic_geohash = geohash::encode(gps_lat, gps_long, geohash::STORED_PRECISION);
+    let date_taken = if prefer_client { None } else { exif.date_taken }; // NULL keeps the current one
 
-    let mut f = File::create(format!("assets/pictures/{:?}.jpg", pic_id)).unwrap(); // create the file with the given id (in url) as name
-    f.write_all(bytes.as_slice()).unwrap(); // write bytes received in the file
+    let byte_size = byte_size as i64;
+    let (width, height) = (width as i32, height as i32); // of the served copy, once upright
+    let orientation = exif.orientation.map(|orientation| orientation as i16);
+    let camera_model = exif.camera_model.map(|model| model.chars().take(CAMERA_MODEL_MAX_LENGTH).collect::<String>());
+    let (exif_gps_lat, exif_gps_long) = (exif.gps.map(|gps| gps.0), exif.gps.map(|gps| gps.1));
 
+    let stmt = try!(conn.prepare("UPDATE pictures
+                            SET uploaded=TRUE, mime_type=$2, width=$3, height=$4, byte_size=$5,
+                                gps_lat=$6, gps_long=$7, geohash=$8, date_taken=COALESCE($9, date_taken),
+                                orientation=$10, camera_model=$11,
+                                exif_gps_lat=$12, exif_gps_long=$13, location_mismatch=$14
+                            WHERE id=$1")); // update the uploaded column and what the upload is
+    try!(stmt.execute(&[&pic_id, &info.format.mime_type(), &width, &height, &byte_size,
+                        &gps_lat, &gps_long, &pic_geohash, &date_taken,
+                        &orientation, &camera_model,
+                        &exif_gps_lat, &exif_gps_long, &location_mismatch]));
 
-    let stmt = conn.prepare("UPDATE pictures
-                            SET uploaded=TRUE
-                            WHERE id=$1").unwrap(); // update the uploaded column
-    stmt.query(&[&pic_id]).unwrap();
+    Ok(String::new())
 }
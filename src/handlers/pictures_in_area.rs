@@ -1,13 +1,56 @@
 use super::prelude::*;
+use super::utils;
+use geo::BoundingBox;
+use postgres::types::ToSql;
+use rustc_serialize::base64;
+use rustc_serialize::base64::{FromBase64, ToBase64};
 
-/// Format the date in the dd/mm/yyyy format.
-fn format_date(date: &chrono::NaiveDate) -> String {
-  format!("{}/{}/{}", date.day(), date.month(), date.year())
+/// SQL expression pages are sorted by, along with the id, for each `order_by`.
+/// They are all DOUBLE PRECISION so that a cursor is always a (f64, i32) pair.
+fn sort_key(order_by: &str) -> Option<&'static str> {
+  match order_by {
+    "likes" => Some("likes::FLOAT8"),
+    "rating" => Some("COALESCE(rating, -1)::FLOAT8"), // unrated pictures come last
+    "date_taken" => Some("EXTRACT(EPOCH FROM date_taken)::FLOAT8"),
+    _ => None,
+  }
+}
+
+/// Position after the last picture of a page.
+struct Cursor {
+  order_by: String,
+  key: f64,
+  id: i32,
 }
 
-pub fn get(req: &mut Request, res: &mut Response) -> String {
+impl Cursor {
+  /// Opaque form handed to the client: url-safe base64 of `order_by|key|id`.
+  fn encode(&self) -> String {
+    let raw = format!("{}|{}|{}", self.order_by, self.key, self.id);
+    raw.as_bytes().to_base64(base64::URL_SAFE)
+  }
+
+  fn decode(cursor: &str) -> Option<Cursor> {
+    let raw = match cursor.from_base64().ok().and_then(|bytes| String::from_utf8(bytes).ok()) {
+      Some(raw) => raw,
+      None => return None,
+    };
+
+    let parts: Vec<&str> = raw.split('|').collect();
+    if parts.len() != 3 {
+      return None;
+    }
+
+    match (parts[1].parse(), parts[2].parse()) {
+      (Ok(key), Ok(id)) => Some(Cursor { order_by: String::from(parts[0]), key: key, id: id }),
+      _ => None,
+    }
+  }
+}
+
+pub fn get(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
   /*
-      get all pictures metadatas in the given area
+      get one page of the pictures metadatas in the given area
   */
 
   // HTTP headers
@@ -15,15 +58,20 @@ pub fn get(req: &mut Request, res: &mut Response) -> String {
   res.set(AccessControlAllowOrigin::Any);
 
   let conn = req.db_conn();
+  let username = try!(req.current_user()).username;
+  let config = req.config();
   let query = req.query();
 
   // get the show type
-  let order_by = query.get("order_by").unwrap();
+  let order_by = match query.get("order_by") {
+    Some(order_by) => order_by,
+    None => return Err(ApiError::Validation(String::from("missing parameter `order_by`"))),
+  };
 
   // order_by content check
-  match order_by {
-    "likes" | "rating" | "date_taken" => {},
-    _ => panic!("bad input")
+  let sort_key = match sort_key(order_by) {
+    Some(sort_key) => sort_key,
+    None => return Err(ApiError::Validation(format!("invalid parameter `order_by`: {}", order_by)))
   };
 
   /*
@@ -34,32 +82,76 @@ pub fn get(req: &mut Request, res: &mut Response) -> String {
   */
 
   // get the border coords
-  let tl_lat: f64 = query.get("tl_lat").unwrap().parse().unwrap();
-  let tl_long: f64 = query.get("tl_long").unwrap().parse().unwrap();
-  let br_lat: f64 = query.get("br_lat").unwrap().parse().unwrap();
-  let br_long: f64 = query.get("br_long").unwrap().parse().unwrap();
-
-  let stmt = conn.prepare(&format!("SELECT * FROM pictures
-                           WHERE gps_long BETWEEN SYMMETRIC $1 AND $2
-                           AND gps_lat BETWEEN SYMMETRIC $3 AND $4
+  let tl_lat: f64 = try!(utils::parse_param("tl_lat", query.get("tl_lat")));
+  let tl_long: f64 = try!(utils::parse_param("tl_long", query.get("tl_long")));
+  let br_lat: f64 = try!(utils::parse_param("br_lat", query.get("br_lat")));
+  let br_long: f64 = try!(utils::parse_param("br_long", query.get("br_long")));
+  let bbox = try!(BoundingBox::from_corners(tl_lat, tl_long, br_lat, br_long));
+
+  // page size, capped server-side
+  let limit = try!(utils::parse_limit(query.get("limit")));
+
+  // where the previous page stopped
+  let cursor = match query.get("cursor") {
+    Some(cursor) => match Cursor::decode(cursor) {
+      Some(ref cursor) if cursor.order_by != order_by => {
+        return Err(ApiError::Validation(String::from("`cursor` was made for another `order_by`")));
+      },
+      Some(cursor) => Some(cursor),
+      None => return Err(ApiError::Validation(String::from("invalid parameter `cursor`"))),
+    },
+    None => None,
+  };
+
+  let fetched = limit + 1; // one more, to know if there is a next page
+  let (in_bbox, bbox_values) = bbox.sql_condition("gps_lat", "gps_long", 2);
+  let (in_cells, cell_patterns) = bbox.geohash_condition("geohash", 2 + bbox_values.len());
+
+  let mut params: Vec<&ToSql> = vec![&fetched];
+  for value in bbox_values.iter() {
+    params.push(value);
+  }
+  for pattern in cell_patterns.iter() {
+    params.push(pattern);
+  }
+  params.push(&username);
+  let liked = db::liked_sql(params.len());
+  let mut after_cursor = String::new();
+  if let Some(ref cursor) = cursor {
+    after_cursor = format!("AND ({}, id) < (${}, ${})", sort_key, params.len() + 1, params.len() + 2);
+    params.push(&cursor.key);
+    params.push(&cursor.id);
+  }
+
+  let stmt = try!(conn.prepare(&format!("SELECT *, {}, {} AS sort_key FROM pictures
+                           WHERE {}
+                           AND {}
                            AND uploaded=TRUE
-                           ORDER BY {} DESC", order_by)).unwrap();  // prepare the query
+                           {}
+                           ORDER BY sort_key DESC, id DESC
+                           LIMIT $1", liked, sort_key, in_cells, in_bbox, after_cursor)));  // prepare the query
 
   let mut pictures = Vec::new(); // create the PictureDBData vector
+  let mut next_cursor = None;
+  let mut last_sort_key = 0.0;
 
   // fill the vector with query's result
-  for row in stmt.query(&[&tl_long, &br_long, &tl_lat, &br_lat]).unwrap() {
-      pictures.push(db::PictureDBData {
-          id: row.get("id"),
-          author: row.get("author"),
-          description: row.get("description"), // optional
-          gps_lat: row.get("gps_lat"),
-          gps_long: row.get("gps_long"),
-          date_taken: format_date(&row.get("date_taken")),
-          rating: row.get("rating"), // optional
-          likes: row.get("likes"),
-      });
+  for row in try!(stmt.query(&params)) {
+      if pictures.len() as i64 == limit {
+          // there is at least one more picture: the page ends at the previous one
+          let last: &db::PictureDBData = &pictures[pictures.len() - 1];
+          next_cursor = Some(Cursor { order_by: String::from(order_by), key: last_sort_key, id: last.id }.encode());
+          break;
+      }
+
+      last_sort_key = row.get::<_, f64>("sort_key");
+      pictures.push(db::PictureDBData::from_row(&row, &config.assets));
   }
 
-  serde_json::ser::to_string(&pictures).unwrap() // return the json value of pictures vec
+  let page = db::PicturePage {
+      items: pictures,
+      next_cursor: next_cursor,
+  };
+
+  Ok(serde_json::ser::to_string(&page).unwrap()) // return the json value of the page
 }
@@ -0,0 +1,88 @@
+use super::prelude::*;
+use super::utils;
+use postgres::types::ToSql;
+use geo;
+use geo::BoundingBox;
+
+/// Largest search radius, in meters.
+pub const MAX_RADIUS_M: f64 = 100_000.0;
+
+/// ORDER BY clause for each accepted `order_by`, closest first on ties.
+fn order_clause(order_by: &str) -> Option<&'static str> {
+  match order_by {
+    "distance" => Some("distance_m ASC, id DESC"),
+    "likes" => Some("likes DESC, distance_m ASC"),
+    "rating" => Some("rating DESC NULLS LAST, distance_m ASC"),
+    "date_taken" => Some("date_taken DESC, distance_m ASC"),
+    _ => None,
+  }
+}
+
+pub fn get(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+  /*
+      get the pictures metadatas within radius_m meters of (lat, long),
+      with their distance to that point
+  */
+
+  // HTTP headers
+  res.set(MediaType::Json); // Content-Type: application/json
+  res.set(AccessControlAllowOrigin::Any);
+
+  let conn = req.db_conn();
+  let username = try!(req.current_user()).username;
+  let config = req.config();
+  let query = req.query();
+
+  let order_by = query.get("order_by").unwrap_or("distance");
+  let order_clause = match order_clause(order_by) {
+    Some(order_clause) => order_clause,
+    None => return Err(ApiError::Validation(format!("invalid parameter `order_by`: {}", order_by))),
+  };
+
+  let lat: f64 = try!(utils::parse_param("lat", query.get("lat")));
+  let long: f64 = try!(utils::parse_param("long", query.get("long")));
+  let radius_m: f64 = try!(utils::parse_param("radius_m", query.get("radius_m")));
+  let limit = try!(utils::parse_limit(query.get("limit")));
+
+  try!(Validator::new()
+           .latitude("lat", lat)
+           .longitude("long", long)
+           .range("radius_m", radius_m, 1.0, MAX_RADIUS_M)
+           .finish());
+
+  // cheap pre-filter on the box around the circle, then the exact distance
+  let bbox = BoundingBox::around(lat, long, radius_m);
+  let (in_bbox, bbox_values) = bbox.sql_condition("gps_lat", "gps_long", 5);
+  let (in_cells, cell_patterns) = bbox.geohash_condition("geohash", 5 + bbox_values.len());
+
+  let mut params: Vec<&ToSql> = vec![&lat, &long, &radius_m, &limit];
+  for value in bbox_values.iter() {
+    params.push(value);
+  }
+  for pattern in cell_patterns.iter() {
+    params.push(pattern);
+  }
+  params.push(&username);
+  let liked = db::liked_sql(params.len());
+
+  let stmt = try!(conn.prepare(&format!("SELECT * FROM (
+                               SELECT *, {}, {} AS distance_m FROM pictures
+                               WHERE {}
+                               AND {}
+                               AND uploaded=TRUE
+                           ) AS nearby
+                           WHERE distance_m <= $3
+                           ORDER BY {}
+                           LIMIT $4", liked, geo::distance_sql("gps_lat", "gps_long", 1, 2), in_cells, in_bbox, order_clause)));
+
+  let mut pictures = Vec::new();
+
+  for row in try!(stmt.query(&params)) {
+      pictures.push(db::NearbyPicture {
+          picture: db::PictureDBData::from_row(&row, &config.assets),
+          distance_m: row.get("distance_m"),
+      });
+  }
+
+  Ok(serde_json::ser::to_string(&pictures).unwrap())
+}
@@ -0,0 +1,63 @@
+/// Who may call a route.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Public, // no session needed
+    Session, // any logged in user
+    Admin, // logged in user with `users.is_admin` set
+}
+
+pub struct Route {
+    pub method: &'static str,
+    pub path: &'static str, // `:name` segments match any value
+    pub access: Access,
+}
+
+/// Authentication policy of every route registered in `main`.
+/// Anything not listed here requires a session.
+pub static ROUTES: &'static [Route] = &[
+    Route { method: "POST", path: "/login", access: Access::Public },
+    Route { method: "POST", path: "/users", access: Access::Public },
+
+    Route { method: "POST", path: "/logout", access: Access::Session },
+    Route { method: "POST", path: "/logout/all", access: Access::Session },
+    Route { method: "GET", path: "/pictures_in_area", access: Access::Session },
+    Route { method: "GET", path: "/pictures_near", access: Access::Session },
+    Route { method: "GET", path: "/picture_clusters", access: Access::Session },
+    Route { method: "POST", path: "/pictures", access: Access::Session },
+    Route { method: "GET", path: "/pictures/:id", access: Access::Session },
+    Route { method: "PUT", path: "/pictures/:id", access: Access::Session },
+    Route { method: "POST", path: "/pictures/:id/like", access: Access::Session },
+    Route { method: "DELETE", path: "/pictures/:id/like", access: Access::Session },
+    Route { method: "PUT", path: "/pictures/:id/rating", access: Access::Session },
+    Route { method: "POST", path: "/users/:username", access: Access::Session },
+];
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    /*
+        compares segment by segment, ignoring a trailing slash
+    */
+    let mut pattern_segments = pattern.trim_right_matches('/').split('/');
+    let mut path_segments = path.trim_right_matches('/').split('/');
+
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return true,
+            (Some(p), Some(s)) => {
+                if !p.starts_with(':') && p != s {
+                    return false;
+                }
+                if p.starts_with(':') && s.is_empty() {
+                    return false;
+                }
+            },
+            _ => return false,
+        }
+    }
+}
+
+/// Access level required by `method` on `path` (without the query string).
+pub fn access_for(method: &str, path: &str) -> Access {
+    ROUTES.iter()
+          .find(|route| route.method == method && path_matches(route.path, path))
+          .map_or(Access::Session, |route| route.access)
+}
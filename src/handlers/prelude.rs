@@ -5,8 +5,11 @@ pub use chrono;
 pub use chrono::*;
 pub use serde_json;
 pub use db;
-pub use octavo::crypto::block::blowfish::bcrypt;
 pub use std::fs::File;
 pub use std::io::prelude::*;
 pub use rustc_serialize::hex::ToHex;
 pub use hyper::header::Cookie;
+pub use error::{ApiError, AuthError, AccessError, ConflictError};
+pub use config::ConfigRequestExtensions;
+pub use super::sessions::{CurrentUser, CurrentUserRequestExtensions};
+pub use validation::{Validate, Validator};
@@ -0,0 +1,67 @@
+use super::prelude::*;
+use super::utils;
+use postgres::Transaction;
+
+fn set_rating(trans: &Transaction, username: &String, pic_id: i32, score: f32) -> Result<db::RatingState, ApiError> {
+    /*
+        stores the user's score, replacing a previous one,
+        then recomputes the picture's average and vote count
+    */
+
+    // lock the picture: concurrent votes on it wait for this transaction
+    let stmt = try!(trans.prepare("SELECT id
+                            FROM pictures
+                            WHERE id = $1
+                            AND uploaded = TRUE
+                            FOR UPDATE"));
+    let rows = try!(stmt.query(&[&pic_id]));
+
+    if rows.len() == 0 {
+        return Err(ApiError::NotFound(format!("no picture with id {}", pic_id)));
+    }
+
+    let stmt = try!(trans.prepare("UPDATE picture_ratings
+                            SET score = $3, date_updated = NOW()
+                            WHERE username = $1 AND picture_id = $2"));
+    if try!(stmt.execute(&[username, &pic_id, &score])) == 0 {
+        let stmt = try!(trans.prepare("INSERT INTO picture_ratings (username, picture_id, score)
+                                VALUES ($1, $2, $3)"));
+        try!(stmt.execute(&[username, &pic_id, &score]));
+    }
+
+    let stmt = try!(trans.prepare("UPDATE pictures
+                            SET rating = (SELECT AVG(score) FROM picture_ratings
+                                          WHERE picture_id = $1)::REAL,
+                                rating_count = (SELECT COUNT(*) FROM picture_ratings
+                                                WHERE picture_id = $1)
+                            WHERE id = $1
+                            RETURNING rating, rating_count"));
+    let rows = try!(stmt.query(&[&pic_id]));
+    let row = rows.get(0);
+
+    Ok(db::RatingState {
+        score: score,
+        rating: row.get("rating"),
+        rating_count: row.get("rating_count"),
+    })
+}
+
+pub fn put(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
+    /*
+        set the caller's score for a picture, one per user
+    */
+    res.set(MediaType::Json); // HTTP header : Content-Type: application/json
+
+    let conn = req.db_conn();
+    let username = try!(req.current_user()).username;
+    let pic_id: i32 = try!(utils::parse_param("id", req.param("id")));
+
+    let rating: db::Rating = try!(serde_json::de::from_reader(&mut req.origin));
+    try!(rating.validate());
+
+    let trans = try!(conn.transaction());
+    let state = try!(set_rating(&trans, &username, pic_id, rating.score));
+    try!(trans.commit());
+
+    Ok(serde_json::ser::to_string(&state).unwrap())
+}
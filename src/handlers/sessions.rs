@@ -4,78 +4,179 @@ use r2d2_postgres::PostgresConnectionManager;
 use octavo::digest::sha2::SHA256;
 use octavo::digest::Digest;
 use rustc_serialize::hex::FromHex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use config::SessionConfig;
+use plugin::Extensible;
+use typemap::Key;
+use super::policy;
+use super::policy::Access;
 
+/// Name of the cookie holding the session token.
+pub const COOKIE_NAME: &'static str = "SESSID";
 
-pub enum SessionStatus {
-    Valid,
-    Invalid,
+/// The user owning the request's session.
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub username: String,
+    pub is_admin: bool,
 }
 
+impl Key for CurrentUser { type Value = CurrentUser; }
 
-pub fn check_session(req: &mut Request) -> SessionStatus {
+pub trait CurrentUserRequestExtensions {
+    /// The authenticated caller, set by the session middleware
+    /// on every route that isn't public.
+    fn current_user(&self) -> Result<CurrentUser, ApiError>;
+}
+
+impl<'mw, 'conn, D> CurrentUserRequestExtensions for Request<'mw, 'conn, D> {
+    fn current_user(&self) -> Result<CurrentUser, ApiError> {
+        self.extensions().get::<CurrentUser>()
+                         .map(|user| user.clone())
+                         .ok_or(ApiError::Auth(AuthError::SessionMissing))
+    }
+}
+
+// unix time of the last purge of expired sessions
+static LAST_SWEEP: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn cookie(value: &str, max_age: i64, config: &SessionConfig) -> String {
+    /*
+        HttpOnly keeps it away from scripts, SameSite from
+        cross-site requests
+    */
+    let mut cookie = format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+                             COOKIE_NAME, value, max_age);
+    if config.secure_cookie {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// Builds the `Set-Cookie` header value handing `token_hex` to the client,
+/// valid for the whole session lifetime
+pub fn session_cookie(token_hex: &str, config: &SessionConfig) -> String {
+    cookie(token_hex, config.lifetime, config)
+}
+
+/// Builds the `Set-Cookie` header value removing the session cookie
+pub fn clear_session_cookie(config: &SessionConfig) -> String {
+    cookie("", 0, config)
+}
+
+/// Hex SHA-256 of a session token, as stored in `sessions.token_hash`
+pub fn hash_token(token: &[u8]) -> String {
+    let mut token_hash_bin: Vec<u8> = vec![0; 32];
+
+    let mut sha2 = SHA256::default();
+    sha2.update(token);
+    sha2.result(&mut token_hash_bin);
+
+    token_hash_bin.to_hex() // serialize to hex
+}
+
+/// Hash of the token in the request's SESSID cookie
+pub fn current_token_hash(req: &Request) -> Result<String, ApiError> {
+    /*
+        fails if there is no SESSID cookie,
+        or if it can't be one of our tokens
+    */
+    let cookie_header = match req.origin.headers.get::<Cookie>() {
+        Some(cookie_header) => cookie_header,
+        None => return Err(ApiError::Auth(AuthError::SessionMissing)),
+    };
+
+    let session_cookie = match cookie_header.0.iter().find(|c| c.name == COOKIE_NAME) {
+        Some(session_cookie) => session_cookie,
+        None => return Err(ApiError::Auth(AuthError::SessionMissing)),
+    };
+
+    match session_cookie.value.from_hex() {
+        Ok(token_bin) => Ok(hash_token(&token_bin)),
+        Err(_) => Err(ApiError::Auth(AuthError::SessionInvalid)), // not even hex
+    }
+}
+
+fn sweep_expired(conn: &PooledConnection<PostgresConnectionManager>, config: &SessionConfig) -> Result<(), ApiError> {
     /*
-        check auth cookie: if it's not
-        valid, redirect to /login
+        purges expired sessions, at most once every sweep_interval
+        whatever the number of requests
     */
+    let now = UTC::now().timestamp() as usize;
+    let last_sweep = LAST_SWEEP.load(Ordering::Relaxed);
 
-    fn is_sessid_valid(conn: &PooledConnection<PostgresConnectionManager>, token: &str) -> bool {
-            /*
-                checks if the given sessid exists in database
-            */
-            // hash the token in sha256
-            let mut token_hash_bin: Vec<u8> = vec![0; 32];
-            let token_bin = match token.from_hex(){
-                Ok(hex) => hex,
-                Err(_) => panic!("invalid sessid"),
-            };
-
-            let mut sha2 = SHA256::default();
-            sha2.update(token_bin);
-            sha2.result(&mut token_hash_bin);
-
-            let token_hash_hex = token_hash_bin.to_hex(); // serialize to hex
-
-            // compare with db's token
-            let stmt = conn.prepare("SELECT EXISTS
-                                    (SELECT 1 FROM sessions WHERE token_hash = $1 LIMIT 1)
-                                    AS exists").unwrap();
-            let rows = stmt.query(&[&token_hash_hex]).unwrap();
-
-            let row = rows.get(0); // getting the first and only one row
-            let is_session_valid: bool = row.get("exists");
-
-            is_session_valid
-            /*
-                XXX: continue this when the swift UI is done
-            */
+    if now < last_sweep + config.sweep_interval as usize {
+        return Ok(());
+    }
+    if LAST_SWEEP.compare_and_swap(last_sweep, now, Ordering::SeqCst) != last_sweep {
+        return Ok(()); // another request is already sweeping
     }
 
+    let stmt = try!(conn.prepare("DELETE FROM sessions
+                            WHERE date_created < NOW() - $1 * INTERVAL '1 second'
+                            OR last_seen < NOW() - $2 * INTERVAL '1 second'"));
+    try!(stmt.execute(&[&(config.lifetime as f64), &(config.idle_timeout as f64)]));
 
+    Ok(())
+}
+
+pub fn check_session(req: &mut Request) -> Result<CurrentUser, ApiError> {
+    /*
+        check auth cookie: fails if there is no SESSID cookie,
+        or if it isn't a live session. returns the session's
+        user otherwise and marks the session as used,
+        which pushes back its idle timeout
+    */
+    let config = req.config();
     let conn = req.db_conn();
 
-    if req.origin.headers.has::<Cookie>() {
-        let cookie_header = req.origin.headers.get::<Cookie>().unwrap();
-        let cookies = &cookie_header.0;
-
-        if let Some(session_cookie) = cookies.iter().find(|c| c.name == "SESSID"){
-            if is_sessid_valid(&conn, &session_cookie.value) {
-                SessionStatus::Valid
-            } else {
-                SessionStatus::Invalid
-            }
-        } else {
-            SessionStatus::Invalid
-        }
-
-        /*
-        for cookie in cookies {
-            match &*cookie.name {
-                "SESSID" => return is_sessid_valid(&conn, &cookie.value),
-                _ => return SessionStatus::Invalid
-            }
-        }
-        */
-    } else {
-        SessionStatus::Invalid
+    try!(sweep_expired(&conn, &config.session));
+
+    let token_hash_hex = try!(current_token_hash(req));
+
+    let stmt = try!(conn.prepare("UPDATE sessions
+                            SET last_seen = NOW()
+                            FROM users
+                            WHERE users.username = sessions.username
+                            AND sessions.token_hash = $1
+                            AND sessions.date_created > NOW() - $2 * INTERVAL '1 second'
+                            AND sessions.last_seen > NOW() - $3 * INTERVAL '1 second'
+                            RETURNING users.username, users.is_admin"));
+    let rows = try!(stmt.query(&[&token_hash_hex,
+                                 &(config.session.lifetime as f64),
+                                 &(config.session.idle_timeout as f64)]));
+
+    if rows.len() == 0 {
+        return Err(ApiError::Auth(AuthError::SessionInvalid)); // unknown or expired
     }
+
+    let row = rows.get(0); // getting the first and only one row
+    Ok(CurrentUser {
+        username: row.get("username"),
+        is_admin: row.get("is_admin"),
+    })
+}
+
+pub fn authorize(req: &mut Request) -> Result<(), ApiError> {
+    /*
+        enforces the route's access level from the policy table,
+        public routes are let through without looking at the cookie.
+        on other routes, the caller is attached to the request
+        as a CurrentUser
+    */
+    let method = req.origin.method.to_string();
+    let path = req.path_without_query().unwrap_or("/").to_owned();
+
+    let access = policy::access_for(&method, &path);
+    if access == Access::Public {
+        return Ok(());
+    }
+
+    let user = try!(check_session(req));
+    if access == Access::Admin && !user.is_admin {
+        return Err(ApiError::Forbidden(AccessError::AdminRequired));
+    }
+
+    req.extensions_mut().insert::<CurrentUser>(user);
+    Ok(())
 }
@@ -1,42 +1,37 @@
 use super::prelude::*;
-use super::utils;
-use rand;
+use super::authz;
+use super::sessions;
+use password;
+use password::Verification;
+use config::PasswordConfig;
+use validation;
 use r2d2::PooledConnection;
 use r2d2_postgres::PostgresConnectionManager;
 
-// TODO: make sure the email & username doesn't already exist
-pub fn create_user(req: &mut Request, res: &mut Response) -> String {
+pub fn create_user(req: &mut Request, res: &mut Response) -> Result<String, ApiError> {
     /*
         user creation handler
     */
     res.set(MediaType::Json); // HTTP header : Content-Type: application/json (for return)
 
     let conn = req.db_conn();
-    let user_data: db::User = serde_json::de::from_reader(&mut req.origin).unwrap();
+    let user_data: db::User = try!(serde_json::de::from_reader(&mut req.origin));
+    try!(user_data.validate());
 
     // hash the password
-    let salt: [u8; 16] = rand::random(); // TODO FIXME XXX An application that requires an entropy source for cryptographic purposes must usr OsRng
-    let salt: &[u8] = &salt;
+    let password_hash = try!(password::hash(&req.config().password, &user_data.password));
 
-    let cost = 10;
-    let mut password_hash_bin: Vec<u8> = vec![0; 24];
-
-    bcrypt(cost, salt, &user_data.password.into_bytes(), &mut password_hash_bin);
-
-    let password_hash = utils::to_base64(&password_hash_bin);
-
-    let stmt = conn.prepare("INSERT INTO users
-                            (username, nick, email, password, date_created, nb_pictures, hypes, salt)
-                            VALUES($1, $2, $3, $4, NOW(), 0, 0, $5)
-                            RETURNING id").unwrap();
+    let stmt = try!(conn.prepare("INSERT INTO users
+                            (username, nick, email, password, date_created, nb_pictures, hypes)
+                            VALUES($1, $2, $3, $4, NOW(), 0, 0)
+                            RETURNING id"));
 
     let rows = stmt.query(&[&user_data.username,
                 &user_data.username,
                 &user_data.email,
-                &password_hash,
-                &salt]);
+                &password_hash]);
 
-    // test if username has already been taken
+    // the unique constraints tell if the username or the email is already taken
     match rows {
         Ok(rows) => {
             let first_and_only_row = rows.get(0); // getting the first and only one row
@@ -44,103 +39,196 @@ pub fn create_user(req: &mut Request, res: &mut Response) -> String {
                 id: first_and_only_row.get("id"),
             };
 
-            serde_json::ser::to_string(&user_id).unwrap() // returning the id in json
+            Ok(serde_json::ser::to_string(&user_id).unwrap()) // returning the id in json
         },
 
-        Err(_) => String::from("{\"code\":\"userame already taken\"}")
+        Err(e) => Err(ApiError::from_users_write(e)),
     }
 
 }
 
-pub fn update_user(req: &mut Request, _res: &mut Response) {
+pub fn update_user(req: &mut Request, _res: &mut Response) -> Result<String, ApiError> {
     /*
         update user handler to update given field
     */
-    fn update_nick(conn: &PooledConnection<PostgresConnectionManager>, username: &String, nick: &serde_json::Value) {
+    fn as_str<'a>(field: &str, value: &'a serde_json::Value) -> Result<&'a str, ApiError> {
+        /*
+            every updatable field is a JSON string
+        */
+        value.as_string().ok_or_else(|| ApiError::Validation(format!("`{}` must be a string", field)))
+    }
+
+    fn update_nick(conn: &PooledConnection<PostgresConnectionManager>, username: &String, nick: &serde_json::Value) -> Result<(), ApiError> {
         /*
             update user's nick with given nick
         */
-        let nick_str = nick.as_string().unwrap();
-        let stmt = conn.prepare("UPDATE users
+        let nick_str = try!(as_str("nick", nick));
+        try!(Validator::new().length("nick", nick_str, 1, validation::NICK_MAX_LENGTH).finish());
+        let stmt = try!(conn.prepare("UPDATE users
                                 SET nick = $1
-                                WHERE username = $2").unwrap();
-        let _rows = stmt.query(&[&nick_str, &username]).unwrap();
+                                WHERE username = $2"));
+        try!(stmt.execute(&[&nick_str, &username]));
+        Ok(())
     }
 
-    fn update_email(conn: &PooledConnection<PostgresConnectionManager>, username: &String, email: &serde_json::Value) {
+    fn update_email(conn: &PooledConnection<PostgresConnectionManager>, username: &String, email: &serde_json::Value) -> Result<(), ApiError> {
         /*
             update user's email with given email
         */
-        let email_str = email.as_string().unwrap();
-        let stmt = conn.prepare("UPDATE users
+        let email_str = try!(as_str("email", email));
+        try!(Validator::new().email("email", email_str).finish());
+        let stmt = try!(conn.prepare("UPDATE users
                                 SET email = $1
-                                WHERE username = $2").unwrap();
-        let _rows = stmt.query(&[&email_str, &username]);
+                                WHERE username = $2"));
+        try!(stmt.execute(&[&email_str, &username]).map_err(ApiError::from_users_write));
+        Ok(())
     }
 
-    fn update_password(conn: &PooledConnection<PostgresConnectionManager>, username: &String, password: &serde_json::Value) {
+    fn update_password(conn: &PooledConnection<PostgresConnectionManager>, config: &PasswordConfig, username: &String, password: &serde_json::Value) -> Result<(), ApiError> {
         /*
             update the user's password with given password
-            TODO: Make sure that the user sends his password
         */
-        let new_password = password.as_string().unwrap();
-        let new_password = String::from(new_password);
-        // get the user's salt
-        let stmt = conn.prepare("SELECT salt
-                                FROM users
-                                WHERE username = $1").unwrap();
-        let rows = stmt.query(&[&username]).unwrap();
-
-        if rows.len() > 0 {
-            let row = rows.get(0);
-            let salt: Vec<u8> = row.get("salt");
-
-            // hash
-            let cost = 10;
-            let mut password_hash_bin: Vec<u8> = vec![0; 24];
-            bcrypt(cost, &salt, &new_password.into_bytes(), &mut password_hash_bin);
-            let password_hash = utils::to_base64(&password_hash_bin);
-
-            let stmt = conn.prepare("UPDATE users
-                                    SET password = $1
-                                    WHERE username = $2").unwrap();
-            let _rows = stmt.query(&[&password_hash, &username]).unwrap();
+        let new_password = try!(as_str("password", password));
+        try!(Validator::new().password("password", new_password).finish());
+        let password_hash = try!(password::hash(config, new_password));
+
+        let stmt = try!(conn.prepare("UPDATE users
+                                SET password = $1, salt = NULL
+                                WHERE username = $2"));
+        try!(stmt.execute(&[&password_hash, &username]));
+        Ok(())
+    }
+
+    fn revoke_other_sessions(conn: &PooledConnection<PostgresConnectionManager>, username: &String, kept_token_hash: &Option<String>) -> Result<(), ApiError> {
+        /*
+            delete every session of the user except the one
+            with the given token hash, if any
+        */
+        let stmt = try!(conn.prepare("DELETE FROM sessions
+                                WHERE username = $1
+                                AND token_hash IS DISTINCT FROM $2"));
+        try!(stmt.execute(&[&username, kept_token_hash]));
+        Ok(())
+    }
+
+    fn delete_user(conn: &PooledConnection<PostgresConnectionManager>, username: &String) -> Result<(), ApiError> {
+        /*
+            delete the given user, taking their likes and scores
+            off the counters and averages of the pictures they rated
+        */
+        let trans = try!(conn.transaction());
+        {
+            let stmt = try!(trans.prepare("UPDATE pictures
+                                    SET likes = likes - 1
+                                    WHERE id IN (SELECT picture_id FROM picture_likes
+                                                 WHERE username = $1)"));
+            try!(stmt.execute(&[&username]));
+
+            let stmt = try!(trans.prepare("UPDATE pictures
+                                    SET rating = (SELECT AVG(score) FROM picture_ratings
+                                                  WHERE picture_id = pictures.id
+                                                  AND username <> $1)::REAL,
+                                        rating_count = rating_count - 1
+                                    WHERE id IN (SELECT picture_id FROM picture_ratings
+                                                 WHERE username = $1)"));
+            try!(stmt.execute(&[&username]));
+
+            let stmt = try!(trans.prepare("DELETE FROM users
+                                    WHERE username = $1"));
+            try!(stmt.execute(&[&username]));
         }
+        try!(trans.commit());
+        Ok(())
     }
 
-    fn delete_user(conn: &PooledConnection<PostgresConnectionManager>, username: &String, password: &serde_json::Value){
+    fn verify_password(conn: &PooledConnection<PostgresConnectionManager>, config: &PasswordConfig, username: &String, password: Option<&str>) -> Result<(), ApiError> {
         /*
-            delete the given user
-            TODO: Make sure that the user sends his password
+            checks the user's current password the same way login does
         */
+        let password = match password {
+            Some(password) => password,
+            None => return Err(ApiError::Validation(String::from("the current password is required"))),
+        };
 
+        let stmt = try!(conn.prepare("SELECT password, salt
+                                FROM users
+                                WHERE username = $1"));
+        let rows = try!(stmt.query(&[&username]));
 
+        if rows.len() == 0 {
+            return Err(ApiError::NotFound(format!("no user named {}", username)));
+        }
 
-        let stmt = conn.prepare("DELETE FROM users
-                                WHERE username = $1").unwrap();
-        let _rows = stmt.query(&[&username]);
+        let row = rows.get(0);
+        let db_password: String = row.get("password");
+        let db_salt: Option<Vec<u8>> = row.get("salt"); // legacy hashes only
+
+        match try!(password::verify(config, password, &db_password, db_salt.as_ref().map(|salt| &salt[..]))) {
+            Verification::Invalid => Err(ApiError::Auth(AuthError::PasswordIncorrect)),
+            Verification::Valid | Verification::ValidNeedsRehash => Ok(()),
+        }
     }
 
 
     let conn = req.db_conn();
+    let config = req.config();
+
+    let username = try!(req.param("username")
+                           .ok_or(ApiError::Validation(String::from("missing username"))))
+                           .to_owned(); // get the username we want to modify
 
-    let username = req.param("username").unwrap().to_owned(); // get the username we want to modify
+    // only the account's owner can modify it
+    let current_user = try!(req.current_user());
+    try!(authz::check_account_owner(&current_user, &username));
 
-    let mut body = vec![];
-    req.origin.read_to_end(&mut body).unwrap();
-    let body_utf8 = String::from_utf8(body).unwrap();
+    // make sure the user exists
+    let stmt = try!(conn.prepare("SELECT 1 FROM users WHERE username = $1"));
+    if try!(stmt.query(&[&username])).len() == 0 {
+        return Err(ApiError::NotFound(format!("no user named {}", username)));
+    }
+
+    let data: serde_json::Value = try!(serde_json::de::from_reader(&mut req.origin));
+    let json_body = try!(data.as_object()
+                             .ok_or(ApiError::Validation(String::from("expected a JSON object"))));
 
-    let data: serde_json::Value = serde_json::from_str(&body_utf8).unwrap();
-    let json_body = data.as_object().unwrap();
+    /*
+        changing the email or the password, and deleting the account
+        require the account's current password: in `current_password`,
+        or as the value of `delete`. admins managing someone else's
+        account don't know it and are exempted.
+    */
+    let is_sensitive = json_body.contains_key("email")
+                    || json_body.contains_key("password")
+                    || json_body.contains_key("delete");
+    let acting_as_admin = current_user.is_admin && current_user.username != username;
+
+    if is_sensitive && !acting_as_admin {
+        let current_password = match json_body.get("delete") {
+            Some(value) => value.as_string(),
+            None => json_body.get("current_password").and_then(|value| value.as_string()),
+        };
+        try!(verify_password(&conn, &config.password, &username, current_password));
+    }
+
+    if json_body.contains_key("delete") {
+        // nothing else to update once the account is gone
+        try!(delete_user(&conn, &username));
+        return Ok(String::new());
+    }
 
     for (key, value) in json_body.iter() {
         match &**key { // check what we want to update
-            "nick" => update_nick(&conn, &username, value),
-            "email" => update_email(&conn, &username, value),
-            "password" => update_password(&conn, &username, value),
-            "delete" => delete_user(&conn, &username, value),
+            "nick" => try!(update_nick(&conn, &username, value)),
+            "email" => try!(update_email(&conn, &username, value)),
+            "password" => {
+                try!(update_password(&conn, &config.password, &username, value));
+                // log out every other device, this one stays logged in
+                let current_token_hash = sessions::current_token_hash(req).ok();
+                try!(revoke_other_sessions(&conn, &username, &current_token_hash));
+            },
             _ => {}
         }
     }
 
+    Ok(String::new())
 }
@@ -1,19 +1,37 @@
-use rustc_serialize::base64::ToBase64;
-use rustc_serialize::base64;
-use rustc_serialize::base64::Config;
+use std::str::FromStr;
+use error::ApiError;
 
-/// Returns the base64 of a hash
-pub fn to_base64(input: &[u8]) -> String {
+/// Parses a query string or URL parameter
+pub fn parse_param<T: FromStr>(name: &str, value: Option<&str>) -> Result<T, ApiError> {
     /*
-        serializes an input into base64
+        fails with a validation error when the parameter
+        is missing or can't be parsed
     */
-    let config = Config {
-        char_set: base64::CharacterSet::Standard,
-        newline: base64::Newline::LF,
-        pad: false,
-        line_length: None
-    };
+    match value {
+        Some(value) => value.parse().map_err(|_| {
+            ApiError::Validation(format!("invalid parameter `{}`: {}", name, value))
+        }),
+        None => Err(ApiError::Validation(format!("missing parameter `{}`", name))),
+    }
+}
+
+/// Number of results returned when the client doesn't ask for a `limit`.
+pub const DEFAULT_LIMIT: i64 = 50;
+/// Largest `limit` accepted, whatever the client asks for.
+pub const MAX_LIMIT: i64 = 200;
 
-    let hash: String = input.to_base64(config);
-    hash
+/// Parses the `limit` query parameter of listings
+pub fn parse_limit(value: Option<&str>) -> Result<i64, ApiError> {
+    /*
+        DEFAULT_LIMIT when missing, capped at MAX_LIMIT,
+        fails when below 1
+    */
+    let limit: i64 = match value {
+        Some(_) => try!(parse_param("limit", value)),
+        None => DEFAULT_LIMIT,
+    };
+    if limit < 1 {
+        return Err(ApiError::Validation(String::from("`limit` must be at least 1")));
+    }
+    Ok(if limit > MAX_LIMIT { MAX_LIMIT } else { limit })
 }
@@ -0,0 +1,73 @@
+//! Smaller versions of the uploaded pictures, for the map and the lists.
+//!
+//! Each one is a JPEG stored next to the published picture as `{id}_{name}.jpg`,
+//! so its URL can be derived from the picture's id alone.
+
+use std::cmp;
+use std::path::Path;
+use image::{DynamicImage, FilterType, GenericImage};
+
+use error::ApiError;
+use imaging;
+use imaging::format::ImageFormat;
+
+/// A size pictures are scaled down to.
+pub struct Derivative {
+    pub name: &'static str,
+    pub max_side: u32, // in pixels, pictures already smaller aren't scaled up
+    pub square: bool, // center-cropped to a square first
+}
+
+pub static DERIVATIVES: &'static [Derivative] = &[
+    Derivative { name: "thumbnail", max_side: 256, square: true },
+    Derivative { name: "medium", max_side: 800, square: false },
+    Derivative { name: "display", max_side: 2048, square: false },
+];
+
+/// Name of the file holding `derivative` for picture `pic_id`.
+pub fn file_name(pic_id: i32, derivative: &Derivative) -> String {
+    format!("{}_{}.jpg", pic_id, derivative.name)
+}
+
+// copies the center square of `image`, and only that
+fn center_square(image: &mut DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let side = cmp::min(width, height);
+    image.crop((width - side) / 2, (height - side) / 2, side, side)
+}
+
+// writes `image` scaled down to fit in max_side x max_side, keeping its aspect ratio
+fn write_fitted(image: &DynamicImage, max_side: u32, path: &Path) -> Result<(), ApiError> {
+    let (width, height) = image.dimensions();
+    if width <= max_side && height <= max_side {
+        imaging::write(image, ImageFormat::Jpeg, path)
+    } else {
+        imaging::write(&image.resize(max_side, max_side, FilterType::Lanczos3), ImageFormat::Jpeg, path)
+    }
+}
+
+/// Writes every derivative of picture `pic_id`, made from `image`,
+/// into `dir`, replacing previous ones. Only what a derivative is made
+/// of gets copied: a shrunk picture, or a square at most `max_side` wide.
+pub fn generate(image: &mut DynamicImage, dir: &Path, pic_id: i32) -> Result<(), ApiError> {
+    for derivative in DERIVATIVES.iter() {
+        let path = dir.join(file_name(pic_id, derivative));
+        let (width, height) = image.dimensions();
+        let side = cmp::min(width, height);
+
+        if derivative.square && width != height {
+            let square = if side > derivative.max_side {
+                // shrink first, so that the shorter side is max_side
+                let max_side = derivative.max_side;
+                center_square(&mut image.resize_exact(width * max_side / side, height * max_side / side, FilterType::Lanczos3))
+            } else {
+                center_square(image)
+            };
+            try!(write_fitted(&square, derivative.max_side, &path));
+        } else {
+            try!(write_fitted(image, derivative.max_side, &path));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,340 @@
+//! The few EXIF fields the server uses, read from a JPEG's APP1 segment.
+//!
+//! EXIF is a TIFF structure: a byte order mark, then directories (IFDs) of
+//! 12-byte entries whose values are inline when they fit in 4 bytes and at
+//! an offset otherwise. Anything malformed is ignored rather than failing
+//! the upload: cameras write all sorts of things in there.
+
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use chrono::NaiveDate;
+
+use error::ApiError;
+
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+
+/// What a picture's EXIF says about it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Exif {
+    pub date_taken: Option<NaiveDate>, // from DateTimeOriginal
+    pub gps: Option<(f64, f64)>, // latitude, longitude
+    pub orientation: Option<u16>, // 1 to 8, 1 being upright
+    pub camera_model: Option<String>,
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value_offset: usize,
+}
+
+/// A TIFF block, with every read bounds-checked.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        let big_endian = if data.starts_with(b"MM\x00\x2a") {
+            true
+        } else if data.starts_with(b"II\x2a\x00") {
+            false
+        } else {
+            return None;
+        };
+        Some(Tiff { data: data, big_endian: big_endian })
+    }
+
+    // `length` bytes from `offset`, if they are all in the block
+    fn bytes(&self, offset: usize, length: usize) -> Option<&'a [u8]> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.data.len() => Some(&self.data[offset..end]),
+            _ => None,
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        self.bytes(offset, 2).map(|bytes| {
+            if self.big_endian { BigEndian::read_u16(bytes) } else { LittleEndian::read_u16(bytes) }
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        self.bytes(offset, 4).map(|bytes| {
+            if self.big_endian { BigEndian::read_u32(bytes) } else { LittleEndian::read_u32(bytes) }
+        })
+    }
+
+    fn first_ifd(&self) -> Option<usize> {
+        self.u32_at(4).map(|offset| offset as usize)
+    }
+
+    fn entries(&self, ifd_offset: usize) -> Vec<Entry> {
+        let entry_count = self.u16_at(ifd_offset).unwrap_or(0) as usize;
+        let mut entries = Vec::new();
+
+        for i in 0..entry_count {
+            let offset = ifd_offset + 2 + 12 * i;
+            let (tag, kind, count) = match (self.u16_at(offset), self.u16_at(offset + 2), self.u32_at(offset + 4)) {
+                (Some(tag), Some(kind), Some(count)) => (tag, kind, count),
+                _ => break,
+            };
+
+            let kind_size: u64 = match kind {
+                1 | 2 | 6 | 7 => 1, // byte, ascii, signed byte, undefined
+                3 | 8 => 2, // short, signed short
+                4 | 9 => 4, // long, signed long
+                5 | 10 => 8, // rational, signed rational
+                _ => continue,
+            };
+            let size = kind_size * count as u64;
+
+            let value_offset = if size <= 4 {
+                offset + 8
+            } else {
+                match self.u32_at(offset + 8) {
+                    Some(value_offset) => value_offset as usize,
+                    None => continue,
+                }
+            };
+
+            entries.push(Entry { tag: tag, kind: kind, count: count, value_offset: value_offset });
+        }
+
+        entries
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != 2 {
+            return None;
+        }
+        self.bytes(entry.value_offset, entry.count as usize)
+            .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+            .map(|text| String::from(text.trim_right_matches('\0').trim()))
+            .and_then(|text| if text.is_empty() { None } else { Some(text) })
+    }
+
+    fn short(&self, entry: &Entry) -> Option<u16> {
+        if entry.kind != 3 || entry.count != 1 {
+            return None;
+        }
+        self.u16_at(entry.value_offset)
+    }
+
+    fn long(&self, entry: &Entry) -> Option<u32> {
+        if entry.kind != 4 || entry.count != 1 {
+            return None;
+        }
+        self.u32_at(entry.value_offset)
+    }
+
+    fn rationals(&self, entry: &Entry) -> Option<Vec<f64>> {
+        if entry.kind != 5 {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        for i in 0..entry.count as usize {
+            let offset = entry.value_offset + 8 * i;
+            match (self.u32_at(offset), self.u32_at(offset + 4)) {
+                (Some(_), Some(0)) => return None,
+                (Some(numerator), Some(denominator)) => values.push(numerator as f64 / denominator as f64),
+                _ => return None,
+            }
+        }
+        Some(values)
+    }
+}
+
+// "YYYY:MM:DD HH:MM:SS"
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let parts: Vec<&str> = text.split(|c| c == ':' || c == ' ').collect();
+    if parts.len() < 3 {
+        return None;
+    }
+
+    match (parts[0].parse(), parts[1].parse(), parts[2].parse()) {
+        (Ok(year), Ok(month), Ok(day)) => NaiveDate::from_ymd_opt(year, month, day),
+        _ => None,
+    }
+}
+
+// degrees, minutes, seconds and a N/S or E/W reference
+fn parse_coordinate(dms: &[f64], reference: &str, negative_ref: &str, max: f64) -> Option<f64> {
+    if dms.len() != 3 {
+        return None;
+    }
+
+    let degrees = dms[0] + dms[1] / 60.0 + dms[2] / 3600.0;
+    let degrees = if reference == negative_ref { -degrees } else { degrees };
+    if degrees.abs() <= max { Some(degrees) } else { None }
+}
+
+/// Reads the EXIF fields out of a TIFF block.
+fn parse(data: &[u8]) -> Exif {
+    let mut exif = Exif::default();
+    let tiff = match Tiff::new(data) {
+        Some(tiff) => tiff,
+        None => return exif,
+    };
+    let ifd0 = match tiff.first_ifd() {
+        Some(ifd0) => ifd0,
+        None => return exif,
+    };
+
+    let mut exif_ifd = None;
+    let mut gps_ifd = None;
+
+    for entry in tiff.entries(ifd0) {
+        match entry.tag {
+            TAG_MODEL => exif.camera_model = tiff.ascii(&entry),
+            TAG_ORIENTATION => exif.orientation = tiff.short(&entry).and_then(|o| if o >= 1 && o <= 8 { Some(o) } else { None }),
+            TAG_EXIF_IFD => exif_ifd = tiff.long(&entry),
+            TAG_GPS_IFD => gps_ifd = tiff.long(&entry),
+            _ => {},
+        }
+    }
+
+    if let Some(exif_ifd) = exif_ifd {
+        for entry in tiff.entries(exif_ifd as usize) {
+            if entry.tag == TAG_DATE_TIME_ORIGINAL {
+                exif.date_taken = tiff.ascii(&entry).and_then(|text| parse_date(&text));
+            }
+        }
+    }
+
+    if let Some(gps_ifd) = gps_ifd {
+        let (mut lat, mut lat_ref, mut long, mut long_ref) = (None, None, None, None);
+        for entry in tiff.entries(gps_ifd as usize) {
+            match entry.tag {
+                TAG_GPS_LATITUDE_REF => lat_ref = tiff.ascii(&entry),
+                TAG_GPS_LATITUDE => lat = tiff.rationals(&entry),
+                TAG_GPS_LONGITUDE_REF => long_ref = tiff.ascii(&entry),
+                TAG_GPS_LONGITUDE => long = tiff.rationals(&entry),
+                _ => {},
+            }
+        }
+
+        if let (Some(lat), Some(lat_ref), Some(long), Some(long_ref)) = (lat, lat_ref, long, long_ref) {
+            let lat = parse_coordinate(&lat, &lat_ref, "S", 90.0);
+            let long = parse_coordinate(&long, &long_ref, "W", 180.0);
+            if let (Some(lat), Some(long)) = (lat, long) {
+                exif.gps = Some((lat, long));
+            }
+        }
+    }
+
+    exif
+}
+
+// up to `n` bytes, fewer if the file ends before
+fn read_up_to<R: Read>(reader: &mut R, n: usize) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::with_capacity(n);
+    try!(reader.by_ref().take(n as u64).read_to_end(&mut bytes));
+    Ok(bytes)
+}
+
+/// Reads the EXIF of the JPEG `reader` holds, `None` if it has none.
+pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Option<Exif>, ApiError> {
+    /*
+        walks the segments before the image data,
+        looking for an APP1 starting with "Exif\0\0"
+    */
+    try!(reader.seek(SeekFrom::Start(2))); // after the SOI marker
+
+    loop {
+        let marker = try!(read_up_to(reader, 4));
+        if marker.len() < 4 || marker[0] != 0xff {
+            return Ok(None);
+        }
+        match marker[1] {
+            0xd9 | 0xda => return Ok(None), // EOI, SOS: no more metadata
+            _ => {},
+        }
+
+        let length = BigEndian::read_u16(&marker[2..4]) as usize;
+        if length < 2 {
+            return Ok(None);
+        }
+
+        if marker[1] == 0xe1 {
+            let payload = try!(read_up_to(reader, length - 2));
+            if payload.starts_with(b"Exif\0\0") {
+                return Ok(Some(parse(&payload[6..])));
+            }
+        } else {
+            try!(reader.seek(SeekFrom::Current(length as i64 - 2)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{parse, Exif};
+
+    // big endian TIFF: IFD0 with model, orientation and the exif and gps
+    // pointers, then the exif IFD, the gps IFD, and the values
+    fn sample() -> Vec<u8> {
+        let mut data = b"MM\x00\x2a\x00\x00\x00\x08".to_vec();
+        // IFD0 at 8: 4 entries, ends at 8 + 2 + 48 + 4 = 62
+        data.extend([0x00, 0x04].iter());
+        data.extend([0x01, 0x10, 0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x8c].iter()); // model at 140
+        data.extend([0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00].iter()); // orientation 6
+        data.extend([0x87, 0x69, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3e].iter()); // exif IFD at 62
+        data.extend([0x88, 0x25, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x50].iter()); // gps IFD at 80
+        data.extend([0, 0, 0, 0].iter());
+        // exif IFD at 62: 1 entry, ends at 62 + 2 + 12 + 4 = 80
+        data.extend([0x00, 0x01].iter());
+        data.extend([0x90, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x92].iter()); // date at 146
+        data.extend([0, 0, 0, 0].iter());
+        // gps IFD at 80: 4 entries, ends at 80 + 2 + 48 + 4 = 134
+        data.extend([0x00, 0x04].iter());
+        data.extend([0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, b'N', 0, 0, 0].iter());
+        data.extend([0x00, 0x02, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0xa6].iter()); // lat at 166
+        data.extend([0x00, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, b'W', 0, 0, 0].iter());
+        data.extend([0x00, 0x04, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0xbe].iter()); // long at 190
+        data.extend([0, 0, 0, 0].iter());
+        data.extend([0; 6].iter()); // padding up to 140
+        data.extend(b"Pixel\0".iter()); // 140
+        data.extend(b"2015:06:21 14:03:00\0".iter()); // 146
+        for &(numerator, denominator) in [(48u32, 1u32), (30, 1), (0, 1), (2, 1), (15, 1), (3000, 100)].iter() {
+            for &value in [numerator, denominator].iter() { // 166 then 190
+                data.extend([(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8].iter());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn reads_every_field() {
+        let exif = parse(&sample());
+        assert_eq!(exif.camera_model, Some(String::from("Pixel")));
+        assert_eq!(exif.orientation, Some(6));
+        assert_eq!(exif.date_taken, NaiveDate::from_ymd_opt(2015, 6, 21));
+
+        let (lat, long) = exif.gps.unwrap();
+        assert!((lat - 48.5).abs() < 1e-9);
+        assert!((long + 2.2583333).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ignores_garbage() {
+        assert_eq!(parse(b"not a tiff"), Exif::default());
+        assert_eq!(parse(b"MM\x00\x2a\xff\xff\xff\xff"), Exif::default());
+
+        let mut truncated = sample();
+        truncated.truncate(100);
+        assert_eq!(parse(&truncated).gps, None);
+    }
+}
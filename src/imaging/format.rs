@@ -0,0 +1,234 @@
+//! Detection of the format and dimensions of an image from its header.
+//!
+//! The format comes from the magic bytes, never from what the client claims,
+//! and the header is walked far enough to read the pixel dimensions, which
+//! also proves the file is an image rather than something renamed to `.jpg`.
+
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder};
+
+use error::ApiError;
+
+const PNG_SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SOI: &'static [u8] = b"\xff\xd8";
+
+/// Largest width or height accepted, in pixels.
+pub const MAX_DIMENSION: u32 = 10_000;
+
+/// Largest number of pixels accepted. Pictures are fully decoded to be
+/// published, turning them upright takes a second buffer, and several
+/// uploads may run at once: at 4 bytes a pixel, this caps an upload at
+/// 2 x 96 MB.
+pub const MAX_PIXELS: u64 = 24_000_000;
+
+/// Formats pictures can be uploaded in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+        }
+    }
+
+    /// Extension of the stored file.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    pub fn from_mime_type(mime_type: &str) -> Option<ImageFormat> {
+        match mime_type {
+            "image/jpeg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// What the header of an image says about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn unsupported(message: &str) -> ApiError {
+    ApiError::UnsupportedMediaType(String::from(message))
+}
+
+// exactly `n` bytes, or an error if the file ends before
+fn read_bytes<R: Read>(reader: &mut R, n: usize) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::with_capacity(n);
+    try!(reader.by_ref().take(n as u64).read_to_end(&mut bytes));
+    if bytes.len() < n {
+        return Err(unsupported("truncated image header"));
+    }
+    Ok(bytes)
+}
+
+fn check_dimensions(format: ImageFormat, width: u32, height: u32) -> Result<ImageInfo, ApiError> {
+    if width == 0 || height == 0 {
+        return Err(unsupported("the image has no pixels"));
+    }
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || width as u64 * height as u64 > MAX_PIXELS {
+        return Err(unsupported("the image is too large"));
+    }
+
+    Ok(ImageInfo { format: format, width: width, height: height })
+}
+
+fn read_png<R: Read>(reader: &mut R) -> Result<ImageInfo, ApiError> {
+    /*
+        the IHDR chunk comes first: length 13, type, width, height,
+        bit depth, color type, compression, filter, interlace
+    */
+    let chunk = try!(read_bytes(reader, 8 + 13));
+    if BigEndian::read_u32(&chunk[0..4]) != 13 || &chunk[4..8] != b"IHDR" {
+        return Err(unsupported("malformed PNG header"));
+    }
+
+    let width = BigEndian::read_u32(&chunk[8..12]);
+    let height = BigEndian::read_u32(&chunk[12..16]);
+    let (bit_depth, color_type) = (chunk[16], chunk[17]);
+
+    let valid_depth = match color_type {
+        0 => [1, 2, 4, 8, 16].contains(&bit_depth), // grayscale
+        3 => [1, 2, 4, 8].contains(&bit_depth), // palette
+        2 | 4 | 6 => [8, 16].contains(&bit_depth), // rgb, gray + alpha, rgba
+        _ => false,
+    };
+    if !valid_depth || chunk[18] != 0 || chunk[19] != 0 || chunk[20] > 1 {
+        return Err(unsupported("malformed PNG header"));
+    }
+
+    check_dimensions(ImageFormat::Png, width, height)
+}
+
+fn read_jpeg<R: Read + Seek>(reader: &mut R) -> Result<ImageInfo, ApiError> {
+    /*
+        walks the segments up to the start of frame one,
+        which holds the dimensions
+    */
+    loop {
+        let mut marker = try!(read_bytes(reader, 2));
+        if marker[0] != 0xff {
+            return Err(unsupported("malformed JPEG header"));
+        }
+        while marker[1] == 0xff { // fill bytes
+            marker[1] = try!(read_bytes(reader, 1))[0];
+        }
+
+        match marker[1] {
+            0xd0...0xd7 | 0x01 => continue, // no payload
+            0xd9 | 0xda => return Err(unsupported("JPEG without a frame header")), // EOI, SOS
+            _ => {},
+        }
+
+        let length = BigEndian::read_u16(&try!(read_bytes(reader, 2))) as usize;
+        if length < 2 {
+            return Err(unsupported("malformed JPEG header"));
+        }
+
+        match marker[1] {
+            // start of frame, except DHT (c4), JPG (c8) and DAC (cc)
+            0xc0...0xcf if marker[1] != 0xc4 && marker[1] != 0xc8 && marker[1] != 0xcc => {
+                if length < 8 {
+                    return Err(unsupported("malformed JPEG frame header"));
+                }
+                // precision, height, width, components
+                let frame = try!(read_bytes(reader, 6));
+                let height = BigEndian::read_u16(&frame[1..3]) as u32;
+                let width = BigEndian::read_u16(&frame[3..5]) as u32;
+                return check_dimensions(ImageFormat::Jpeg, width, height);
+            },
+            _ => { try!(reader.seek(SeekFrom::Current(length as i64 - 2))); },
+        }
+    }
+}
+
+/// Sniffs the format of the image `reader` starts with, and reads its
+/// dimensions. Fails with `UnsupportedMediaType` for anything that isn't
+/// a well-formed JPEG or PNG.
+pub fn detect<R: Read + Seek>(reader: &mut R) -> Result<ImageInfo, ApiError> {
+    let magic = try!(read_bytes(reader, 12).map_err(|_| unsupported("the file is too short to be an image")));
+
+    if magic.starts_with(PNG_SIGNATURE) {
+        try!(reader.seek(SeekFrom::Start(PNG_SIGNATURE.len() as u64)));
+        read_png(reader)
+    } else if magic.starts_with(JPEG_SOI) {
+        try!(reader.seek(SeekFrom::Start(JPEG_SOI.len() as u64)));
+        read_jpeg(reader)
+    } else if &magic[0..4] == b"RIFF" && &magic[8..12] == b"WEBP" {
+        Err(unsupported("WebP images aren't supported, upload a JPEG or a PNG"))
+    } else if &magic[4..8] == b"ftyp" {
+        Err(unsupported("HEIC images aren't supported, upload a JPEG or a PNG"))
+    } else {
+        Err(unsupported("not a JPEG or PNG image"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::{detect, ImageFormat, ImageInfo};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        bytes.extend([(width >> 24) as u8, (width >> 16) as u8, (width >> 8) as u8, width as u8].iter());
+        bytes.extend([(height >> 24) as u8, (height >> 16) as u8, (height >> 8) as u8, height as u8].iter());
+        bytes.extend([8, 2, 0, 0, 0, 0, 0, 0, 0].iter()); // rgb, then a fake crc
+        bytes
+    }
+
+    #[test]
+    fn detects_png() {
+        let info = detect(&mut Cursor::new(png(640, 480))).unwrap();
+        assert_eq!(info, ImageInfo { format: ImageFormat::Png, width: 640, height: 480 });
+    }
+
+    #[test]
+    fn detects_jpeg_after_app_segments() {
+        let mut bytes = vec![0xff, 0xd8];
+        bytes.extend([0xff, 0xe0, 0x00, 0x06, b'J', b'F', b'I', b'F'].iter()); // APP0
+        bytes.extend([0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xe0, 0x02, 0x80, 0x03].iter()); // SOF0 480x640
+        bytes.extend(vec![0; 9].iter());
+
+        let info = detect(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(info, ImageInfo { format: ImageFormat::Jpeg, width: 640, height: 480 });
+    }
+
+    #[test]
+    fn rejects_jpeg_without_frame() {
+        let bytes = vec![0xff, 0xd8, 0xff, 0xda, 0x00, 0x02, 0, 0, 0, 0, 0, 0];
+        assert!(detect(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn rejects_other_content() {
+        assert!(detect(&mut Cursor::new(b"<!DOCTYPE html><html></html>".to_vec())).is_err());
+        assert!(detect(&mut Cursor::new(b"MZ\x90\x00\x03\x00\x00\x00\x04\x00\x00\x00".to_vec())).is_err());
+        assert!(detect(&mut Cursor::new(b"RIFF\x00\x00\x00\x00WEBPVP8 ".to_vec())).is_err());
+        assert!(detect(&mut Cursor::new(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_pixels() {
+        assert!(detect(&mut Cursor::new(png(6_000, 4_000))).is_ok());
+        assert!(detect(&mut Cursor::new(png(6_000, 4_001))).is_err());
+        assert!(detect(&mut Cursor::new(png(10_001, 100))).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_png() {
+        assert!(detect(&mut Cursor::new(png(0, 480))).is_err());
+    }
+}
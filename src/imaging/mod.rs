@@ -0,0 +1,85 @@
+//! Handling of uploaded picture binaries.
+
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use image;
+use image::DynamicImage;
+use image::jpeg::JPEGEncoder;
+use image::png::PNGEncoder;
+use rand;
+
+use error::ApiError;
+use self::format::ImageFormat;
+
+pub mod format;
+pub mod derivatives;
+pub mod exif;
+pub mod publish;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Decodes the `format` image at `path`.
+pub fn decode(path: &Path, format: ImageFormat) -> Result<DynamicImage, ApiError> {
+    let reader = BufReader::new(try!(File::open(path)));
+    let image_format = match format {
+        ImageFormat::Jpeg => image::ImageFormat::JPEG,
+        ImageFormat::Png => image::ImageFormat::PNG,
+    };
+
+    image::load(reader, image_format)
+        .map_err(|e| ApiError::UnsupportedMediaType(format!("the image can't be decoded: {}", e)))
+}
+
+/// Encodes `image` as `format` into `path`, with nothing but the pixels.
+/// Writes next to `path` then renames, so clients never load a half-written file.
+pub fn write(image: &DynamicImage, format: ImageFormat, path: &Path) -> Result<(), ApiError> {
+    // unique per call, so concurrent uploads of the same picture don't mix
+    let temp_path = path.with_extension(format!("{}.{:x}.tmp", format.extension(), rand::random::<u64>()));
+    let result = encode(image, format, &temp_path)
+        .and_then(|_| fs::rename(&temp_path, path).map_err(ApiError::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// encodes the decoded buffer itself when the encoder takes its pixel
+// layout, converting only otherwise: a full-size copy can take hundreds
+// of megabytes
+fn encode(image: &DynamicImage, format: ImageFormat, path: &Path) -> Result<(), ApiError> {
+    let file = try!(File::create(path));
+    let mut writer = BufWriter::new(file);
+    match (format, image) {
+        (ImageFormat::Jpeg, &DynamicImage::ImageRgb8(ref rgb)) => {
+            try!(JPEGEncoder::new_with_quality(&mut writer, JPEG_QUALITY)
+                     .encode(rgb, rgb.width(), rgb.height(), image::ColorType::RGB(8)));
+        },
+        (ImageFormat::Jpeg, &DynamicImage::ImageLuma8(ref gray)) => {
+            try!(JPEGEncoder::new_with_quality(&mut writer, JPEG_QUALITY)
+                     .encode(gray, gray.width(), gray.height(), image::ColorType::Gray(8)));
+        },
+        (ImageFormat::Jpeg, _) => { // JPEG has no alpha channel
+            let rgb = image.to_rgb();
+            try!(JPEGEncoder::new_with_quality(&mut writer, JPEG_QUALITY)
+                     .encode(&rgb, rgb.width(), rgb.height(), image::ColorType::RGB(8)));
+        },
+        (ImageFormat::Png, &DynamicImage::ImageRgb8(ref rgb)) => {
+            try!(PNGEncoder::new(&mut writer).encode(rgb, rgb.width(), rgb.height(), image::ColorType::RGB(8)));
+        },
+        (ImageFormat::Png, &DynamicImage::ImageRgba8(ref rgba)) => {
+            try!(PNGEncoder::new(&mut writer).encode(rgba, rgba.width(), rgba.height(), image::ColorType::RGBA(8)));
+        },
+        (ImageFormat::Png, &DynamicImage::ImageLuma8(ref gray)) => {
+            try!(PNGEncoder::new(&mut writer).encode(gray, gray.width(), gray.height(), image::ColorType::Gray(8)));
+        },
+        (ImageFormat::Png, &DynamicImage::ImageLumaA8(ref gray_alpha)) => {
+            try!(PNGEncoder::new(&mut writer)
+                     .encode(gray_alpha, gray_alpha.width(), gray_alpha.height(), image::ColorType::GrayA(8)));
+        },
+    }
+    try!(writer.flush());
+    try!(writer.get_ref().sync_all());
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Turning an upload into the files served to everyone.
+//!
+//! Uploads carry EXIF, XMP and IPTC blocks holding the author's location,
+//! device and timestamps. The served picture is re-encoded from its pixels
+//! alone, so none of them survive, with the EXIF orientation applied to the
+//! pixels since the tag that told viewers to rotate it is gone. Originals
+//! are kept untouched in a directory that isn't served.
+
+use std::path::Path;
+use image::{DynamicImage, GenericImage};
+
+use error::ApiError;
+use imaging;
+use imaging::derivatives;
+use imaging::format::ImageFormat;
+
+// applies `transform` to `image`, which is freed as soon as it's done
+fn step<F: Fn(&DynamicImage) -> DynamicImage>(image: DynamicImage, transform: F) -> DynamicImage {
+    transform(&image)
+}
+
+/// Turns `image` upright according to its EXIF `orientation`. Rotating
+/// takes a second buffer, but never more: each step frees the one before.
+pub fn orient(image: DynamicImage, orientation: Option<u16>) -> DynamicImage {
+    let orientation = orientation.unwrap_or(1);
+
+    // 4, 5 and 7 are mirrored: rotated like 3, 6 and 8, then flipped
+    let image = match orientation {
+        3 | 4 => step(image, DynamicImage::rotate180),
+        5 | 6 => step(image, DynamicImage::rotate90),
+        7 | 8 => step(image, DynamicImage::rotate270),
+        _ => image,
+    };
+
+    match orientation {
+        2 | 4 | 5 | 7 => step(image, DynamicImage::fliph),
+        _ => image,
+    }
+}
+
+/// Writes the served copy of picture `pic_id` and its derivatives into
+/// `pictures_dir`, from the `format` upload at `original`. Returns the
+/// dimensions of the served copy.
+pub fn publish(original: &Path, format: ImageFormat, orientation: Option<u16>, pictures_dir: &Path, pic_id: i32) -> Result<(u32, u32), ApiError> {
+    let mut image = orient(try!(imaging::decode(original, format)), orientation);
+
+    try!(derivatives::generate(&mut image, pictures_dir, pic_id));
+    try!(imaging::write(&image, format, &pictures_dir.join(format!("{}.{}", pic_id, format.extension()))));
+
+    Ok(image.dimensions())
+}
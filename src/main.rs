@@ -13,60 +13,361 @@ extern crate serde_json; // JSON
 extern crate r2d2; // pool of threads
 extern crate r2d2_postgres;
 extern crate octavo;
+extern crate argon2; // password hashing
+extern crate bcrypt;
 extern crate rand; // for password entropy
 extern crate byteorder;
 extern crate cookie;
+extern crate toml; // configuration file
+extern crate typemap; // request extensions
+extern crate plugin;
+extern crate image; // picture derivatives
 
 use nickel::{
   Nickel, HttpRouter, StaticFilesHandler
 };
-use postgres::SslMode;
+use postgres::{Connection, SslMode};
 use nickel_postgres::{PostgresMiddleware};
 use r2d2::NopErrorHandler;
 
-pub use nickel::MediaType;
-use nickel::status::StatusCode;
 use nickel::Action;
 
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 
+use config::{Config, ConfigMiddleware};
+use error::ApiError;
+use imaging::{derivatives, exif, format, publish};
+use imaging::format::ImageFormat;
+
+
+pub mod config;
 pub mod db;
+pub mod error;
+pub mod geo;
+pub mod imaging;
+pub mod password;
+pub mod validation;
 mod handlers;
 
+// route a handler returning `Result<String, ApiError>`
+macro_rules! api_handler {
+    ($handler:path) => (middleware! { |req, mut res| {
+        let result = $handler(req, &mut res);
+        handlers::respond(&mut res, result)
+    }})
+}
+
+fn print_usage() {
+    println!("usage: server [--config PATH] [migrate [up [VERSION] | down VERSION | status] | derivatives [ID...]]");
+}
+
+fn migrate(config: &Config, args: &[String]) -> Result<(), String> {
+    /*
+        `migrate` subcommand: apply, revert or list schema migrations
+    */
+    let conn = try!(Connection::connect(&config.database.url[..], &SslMode::None).map_err(|e| e.to_string()));
+
+    let parse_version = |arg: Option<&String>| -> Result<Option<i32>, String> {
+        match arg {
+            Some(v) => v.parse().map(Some).map_err(|_| format!("invalid version: {}", v)),
+            None => Ok(None),
+        }
+    };
+
+    match args.get(0).map(|s| &s[..]) {
+        None | Some("up") => {
+            let target = try!(parse_version(args.get(1)));
+            let applied = try!(db::migrations::migrate_up(&conn, target).map_err(|e| e.to_string()));
+            for version in applied {
+                println!("applied migration {}", version);
+            }
+        },
+        Some("down") => {
+            let target = match try!(parse_version(args.get(1))) {
+                Some(target) => target,
+                None => return Err(String::from("`migrate down` needs a target version (0 reverts everything)")),
+            };
+            let reverted = try!(db::migrations::migrate_down(&conn, target).map_err(|e| e.to_string()));
+            for version in reverted {
+                println!("reverted migration {}", version);
+            }
+        },
+        Some("status") => {
+            let applied = try!(db::migrations::applied_versions(&conn).map_err(|e| e.to_string()));
+            for migration in db::migrations::MIGRATIONS.iter() {
+                let state = if applied.contains(&migration.version) { "applied" } else { "pending" };
+                println!("{:>4} {} ({})", migration.version, migration.name, state);
+            }
+        },
+        Some(other) => return Err(format!("unknown migrate command: {}", other)),
+    }
+
+    Ok(())
+}
+
+fn read_orientation(path: &Path, format: ImageFormat) -> Result<Option<u16>, ApiError> {
+    match format {
+        ImageFormat::Jpeg => Ok(try!(exif::read(&mut try!(File::open(path)))).and_then(|exif| exif.orientation)),
+        ImageFormat::Png => Ok(None),
+    }
+}
+
+/// Files of a picture uploaded before originals were kept apart: its
+/// served file still holds the upload, metadata included.
+struct Legacy {
+    served: PathBuf,
+}
+
+fn find_legacy(originals_dir: &Path, pictures_dir: &Path, id: i32, stored_format: Option<ImageFormat>) -> Option<Legacy> {
+    /*
+        older servers stored any body as {id}.jpg, without
+        recording its format
+    */
+    let file_name = format!("{}.{}", id, stored_format.unwrap_or(ImageFormat::Jpeg).extension());
+    let served = pictures_dir.join(&file_name);
+    if fs::metadata(originals_dir.join(&file_name)).is_err() && fs::metadata(&served).is_ok() {
+        Some(Legacy { served: served })
+    } else {
+        None
+    }
+}
+
+fn republish_one(originals_dir: &Path, pictures_dir: &Path, id: i32, stored_format: Option<ImageFormat>, legacy: Option<&Legacy>) -> Result<(ImageFormat, u32, u32), ApiError> {
+    /*
+        publishes one picture from its original, which is its
+        served copy if the original wasn't kept apart yet.
+        returns the format of the original and the dimensions
+        of the served copy
+    */
+    let format = match legacy {
+        // trust the content, not the name
+        Some(legacy) => try!(format::detect(&mut try!(File::open(&legacy.served)))).format,
+        None => stored_format.unwrap_or(ImageFormat::Jpeg),
+    };
+    let original = originals_dir.join(format!("{}.{}", id, format.extension()));
+
+    if let Some(legacy) = legacy {
+        try!(fs::copy(&legacy.served, &original));
+    }
+
+    let result = read_orientation(&original, format)
+        .and_then(|orientation| publish::publish(&original, format, orientation, pictures_dir, id));
+
+    match (result, legacy) {
+        (Ok((width, height)), Some(legacy)) => {
+            if legacy.served != pictures_dir.join(format!("{}.{}", id, format.extension())) {
+                // a PNG stored as {id}.jpg, now served as {id}.png
+                try!(fs::remove_file(&legacy.served));
+            }
+            Ok((format, width, height))
+        },
+        (Ok((width, height)), None) => Ok((format, width, height)),
+        (Err(e), legacy) => {
+            if legacy.is_some() {
+                let _ = fs::remove_file(&original);
+            }
+            Err(e)
+        },
+    }
+}
+
+fn quarantine(conn: &Connection, originals_dir: &Path, pictures_dir: &Path, id: i32, legacy: &Legacy) -> Result<(), String> {
+    /*
+        a legacy upload that can't be republished: it's
+        moved out of the served directory, and the picture
+        waits for a new upload like one never uploaded
+    */
+    let kept = originals_dir.join(format!("{}.quarantined", id));
+    try!(fs::copy(&legacy.served, &kept).map_err(|e| e.to_string()));
+    try!(fs::remove_file(&legacy.served).map_err(|e| e.to_string()));
+    for derivative in derivatives::DERIVATIVES.iter() {
+        let _ = fs::remove_file(pictures_dir.join(derivatives::file_name(id, derivative)));
+    }
+
+    try!(conn.execute("UPDATE pictures SET uploaded=FALSE WHERE id=$1", &[&id]).map_err(|e| e.to_string()));
+    Ok(())
+}
+
+fn republish_where<F: Fn(i32, bool) -> bool>(config: &Config, selected: F) -> Result<usize, String> {
+    /*
+        rebuilds the served copy and the derivatives of the
+        uploaded pictures `selected` accepts, given their id and
+        whether they are legacy uploads. legacy uploads that fail
+        are quarantined rather than left served with their metadata.
+        returns how many pictures failed
+    */
+    let conn = try!(Connection::connect(&config.database.url[..], &SslMode::None).map_err(|e| e.to_string()));
+    let stmt = try!(conn.prepare("SELECT id, mime_type FROM pictures WHERE uploaded=TRUE ORDER BY id")
+                        .map_err(|e| e.to_string()));
+    let rows = try!(stmt.query(&[]).map_err(|e| e.to_string()));
+    let update = try!(conn.prepare("UPDATE pictures SET mime_type=$2, width=$3, height=$4 WHERE id=$1")
+                          .map_err(|e| e.to_string()));
+
+    let pictures_dir = Path::new(&config.assets.pictures_dir);
+    let originals_dir = Path::new(&config.assets.originals_dir);
+    let mut failures = 0;
+
+    for row in rows.iter() {
+        let id: i32 = row.get("id");
+        let mime_type: Option<String> = row.get("mime_type");
+        let stored_format = mime_type.as_ref().and_then(|mime| ImageFormat::from_mime_type(mime));
+        let legacy = find_legacy(originals_dir, pictures_dir, id, stored_format);
+
+        if !selected(id, legacy.is_some()) {
+            continue;
+        }
+
+        match republish_one(originals_dir, pictures_dir, id, stored_format, legacy.as_ref()) {
+            Ok((format, width, height)) => {
+                try!(update.execute(&[&id, &format.mime_type(), &(width as i32), &(height as i32)]).map_err(|e| e.to_string()));
+                println!("republished picture {}", id);
+            },
+            Err(e) => {
+                failures += 1;
+                match legacy {
+                    Some(ref legacy) => {
+                        try!(quarantine(&conn, originals_dir, pictures_dir, id, legacy));
+                        println!("picture {}: {}; quarantined, it needs a new upload", id, e.message());
+                    },
+                    None => println!("picture {}: {}", id, e.message()),
+                }
+            },
+        }
+    }
+
+    Ok(failures)
+}
+
+fn republish(config: &Config, args: &[String]) -> Result<(), String> {
+    /*
+        `derivatives` subcommand: rebuild the served copy and the
+        derivatives of the given uploaded pictures, or of all of them.
+        pictures uploaded before originals were kept apart get their
+        served copy moved to the originals first
+    */
+    let mut ids = Vec::new();
+    for arg in args {
+        ids.push(try!(arg.parse::<i32>().map_err(|_| format!("invalid picture id: {}", arg))));
+    }
+
+    try!(config.create_dirs());
+    let failures = try!(republish_where(config, |id, _| ids.is_empty() || ids.contains(&id)));
+    if failures > 0 {
+        return Err(format!("{} pictures failed", failures));
+    }
+    Ok(())
+}
+
+fn republish_legacy(config: &Config) -> Result<usize, String> {
+    /*
+        pictures uploaded before originals were kept apart are
+        served with their metadata until they are republished,
+        so this runs before serving anything
+    */
+    republish_where(config, |_, legacy| legacy)
+}
+
 fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let mut config_path = None;
+    if args.len() >= 2 && args[0] == "--config" {
+        config_path = Some(args[1].clone());
+        args = args[2..].to_vec();
+    }
+
+    let config = match Config::load(config_path.as_ref().map(|s| &s[..])) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    match args.get(0).map(|s| &s[..]) {
+        Some("migrate") => {
+            if let Err(e) = migrate(&config, &args[1..]) {
+                println!("migration failed: {}", e);
+                process::exit(1);
+            }
+            return;
+        },
+        Some("derivatives") => {
+            if let Err(e) = republish(&config, &args[1..]) {
+                println!("republishing failed: {}", e);
+                process::exit(1);
+            }
+            return;
+        },
+        Some(_) => {
+            print_usage();
+            process::exit(2);
+        },
+        None => {}
+    }
+
+    // bring the schema up to date before serving anything
+    if config.database.auto_migrate {
+        if let Err(e) = migrate(&config, &[]) {
+            println!("migration failed: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if let Err(e) = config.create_dirs() {
+        println!("invalid configuration: {}", e);
+        process::exit(1);
+    }
+
+    // failed pictures are quarantined, the others are served as usual
+    match republish_legacy(&config) {
+        Ok(0) => {},
+        Ok(failures) => println!("{} pictures uploaded with their metadata couldn't be republished", failures),
+        Err(e) => {
+            println!("republishing pictures uploaded with their metadata failed: {}", e);
+            process::exit(1);
+        },
+    }
+
     let dbpool = PostgresMiddleware::new(
-      "postgresql://postgres:@127.0.0.1/hypest",
+      &config.database.url[..],
       SslMode::None,
-      5,
+      config.database.pool_size,
       Box::new(NopErrorHandler)
     ).unwrap();
 
+    let config = Arc::new(config);
+
     let mut server = Nickel::new();
-    server.utilize(StaticFilesHandler::new("assets"));
+    server.utilize(StaticFilesHandler::new(&config.assets.dir[..]));
     server.utilize(dbpool);
+    server.utilize(ConfigMiddleware::new(config.clone()));
     server.utilize(middleware! { |req, mut res|
-        match handlers::sessions::check_session(req) {
-            handlers::sessions::SessionStatus::Valid => println!("sessid ok"),
-            handlers::sessions::SessionStatus::Invalid => {
-                res.set(StatusCode::Forbidden);
-                return res.send("");
-            }
+        if let Err(e) = handlers::sessions::authorize(req) {
+            let body = handlers::respond(&mut res, Err(e));
+            return res.send(body);
         }
     });
 
-    server.get("/pictures_in_area", middleware! { |req, mut res| handlers::pictures_in_area::get(req, &mut res) } );
-    server.post("/pictures", middleware! { |req, mut res| handlers::pictures::post(req, &mut res) });
-    server.put("/pictures/:id", middleware! { |req, mut res| handlers::pictures::put(req, &mut res) });
-    server.post("/users", middleware! { |req, mut res| handlers::users::create_user(req, &mut res) });
-    server.post("/users/:username", middleware! { |req, mut res| handlers::users::update_user(req, &mut res) });
-    server.post("/login", middleware! { |req, mut res| {
-      res.set(MediaType::Json); // HTTP header : Content-Type: application/json
-
-      match handlers::login::post(req, &mut res) {
-        handlers::login::LoginStatus::LoginOk => "{\"code\":\"LoginOk\"}",
-        handlers::login::LoginStatus::EmailIncorrect => "{\"code\":\"EmailIncorrect\"}",
-        handlers::login::LoginStatus::PasswordIncorrect => "{\"code\":\"PasswordIncorrect\"}",
-      }
-    }});
-
-    server.listen("127.0.0.1:6767"); // listen
+    server.get("/pictures_in_area", api_handler!(handlers::pictures_in_area::get));
+    server.get("/pictures_near", api_handler!(handlers::pictures_near::get));
+    server.get("/picture_clusters", api_handler!(handlers::picture_clusters::get));
+    server.post("/pictures", api_handler!(handlers::pictures::post));
+    server.get("/pictures/:id", api_handler!(handlers::pictures::get));
+    server.put("/pictures/:id", api_handler!(handlers::pictures::put));
+    server.post("/pictures/:id/like", api_handler!(handlers::likes::post));
+    server.delete("/pictures/:id/like", api_handler!(handlers::likes::delete));
+    server.put("/pictures/:id/rating", api_handler!(handlers::ratings::put));
+    server.post("/users", api_handler!(handlers::users::create_user));
+    server.post("/users/:username", api_handler!(handlers::users::update_user));
+    server.post("/login", api_handler!(handlers::login::post));
+    server.post("/logout", api_handler!(handlers::logout::post));
+    server.post("/logout/all", api_handler!(handlers::logout::post_all));
+
+    server.listen(&config.server.bind_address[..]); // listen
 }
@@ -0,0 +1,121 @@
+//! Password hashing.
+//!
+//! Hashes are stored as self-describing PHC strings
+//! (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`, or `$2b$cost$...` for bcrypt),
+//! so the algorithm and its cost can change without breaking existing rows.
+//! Rows written before that hold a base64 bcrypt output with the salt in a
+//! separate `salt` column; they still verify, and get re-hashed at login.
+
+use argon2;
+use bcrypt;
+use octavo::crypto::block::blowfish::bcrypt as legacy_bcrypt;
+use rand::Rng;
+use rand::os::OsRng;
+use rustc_serialize::base64;
+use rustc_serialize::base64::ToBase64;
+
+use config::PasswordConfig;
+use error::ApiError;
+
+/// Outcome of checking a password against its stored hash.
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    ValidNeedsRehash, // correct, but stored with an outdated algorithm or cost
+}
+
+fn argon2_config(config: &PasswordConfig) -> argon2::Config<'static> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: config.argon2_memory_kib,
+        time_cost: config.argon2_iterations,
+        lanes: config.argon2_parallelism,
+        ..argon2::Config::default()
+    }
+}
+
+/// Hashes `password` with the configured algorithm into a PHC string.
+pub fn hash(config: &PasswordConfig, password: &str) -> Result<String, ApiError> {
+    match &config.algorithm[..] {
+        "bcrypt" => bcrypt::hash(password, config.bcrypt_cost)
+                        .map_err(|e| ApiError::Storage(format!("bcrypt hashing failed: {}", e))),
+        _ => {
+            let mut salt = [0u8; 16];
+            let mut rng = try!(OsRng::new());
+            rng.fill_bytes(&mut salt);
+
+            argon2::hash_encoded(password.as_bytes(), &salt, &argon2_config(config))
+                .map_err(|e| ApiError::Storage(format!("argon2 hashing failed: {}", e)))
+        }
+    }
+}
+
+/// Checks `password` against `stored`, the content of `users.password`.
+/// `legacy_salt` is `users.salt`, only set on rows predating PHC hashes.
+pub fn verify(config: &PasswordConfig, password: &str, stored: &str, legacy_salt: Option<&[u8]>) -> Result<Verification, ApiError> {
+    let is_valid = if stored.starts_with("$argon2") {
+        try!(argon2::verify_encoded(stored, password.as_bytes())
+                 .map_err(|e| ApiError::Storage(format!("unreadable argon2 hash: {}", e))))
+    } else if stored.starts_with("$2") {
+        try!(bcrypt::verify(password, stored)
+                 .map_err(|e| ApiError::Storage(format!("unreadable bcrypt hash: {}", e))))
+    } else {
+        match legacy_salt {
+            Some(salt) => constant_time_eq(legacy_hash(salt, password).as_bytes(), stored.as_bytes()),
+            None => return Err(ApiError::Storage(String::from("legacy password hash without a salt"))),
+        }
+    };
+
+    if !is_valid {
+        Ok(Verification::Invalid)
+    } else if needs_rehash(config, stored) {
+        Ok(Verification::ValidNeedsRehash)
+    } else {
+        Ok(Verification::Valid)
+    }
+}
+
+fn needs_rehash(config: &PasswordConfig, stored: &str) -> bool {
+    /*
+        true for legacy hashes, hashes made with the other algorithm,
+        and hashes made with different cost parameters
+    */
+    match &config.algorithm[..] {
+        "bcrypt" => {
+            let cost = stored.split('$').nth(2).and_then(|cost| cost.parse::<u32>().ok());
+            !stored.starts_with("$2") || cost != Some(config.bcrypt_cost)
+        },
+        _ => {
+            let params = format!("$m={},t={},p={}$", config.argon2_memory_kib,
+                                                     config.argon2_iterations,
+                                                     config.argon2_parallelism);
+            !stored.starts_with("$argon2id$") || !stored.contains(&params[..])
+        }
+    }
+}
+
+// hash format used before PHC strings: bcrypt with a cost of 10,
+// 24 bytes of output, unpadded base64
+fn legacy_hash(salt: &[u8], password: &str) -> String {
+    let cost = 10;
+    let mut password_hash_bin: Vec<u8> = vec![0; 24];
+
+    legacy_bcrypt(cost, salt, password.as_bytes(), &mut password_hash_bin);
+
+    password_hash_bin.to_base64(base64::Config {
+        char_set: base64::CharacterSet::Standard,
+        newline: base64::Newline::LF,
+        pad: false,
+        line_length: None
+    })
+}
+
+/// Compares two byte strings in a time that only depends on their length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,242 @@
+//! Validation of inbound payloads.
+//!
+//! Payloads implement `Validate` by listing the rules each of their fields
+//! must follow on a `Validator`, which collects every failure so the client
+//! gets them all at once, field by field.
+
+use db;
+use error::ApiError;
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 30;
+pub const EMAIL_MAX_LENGTH: usize = 254;
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+pub const NICK_MAX_LENGTH: usize = 50;
+pub const DESCRIPTION_MAX_LENGTH: usize = 500;
+pub const RATING_MIN: f32 = 0.0;
+pub const RATING_MAX: f32 = 5.0;
+
+/// A field that failed validation.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+pub trait Validate {
+    /// Fails with `ApiError::InvalidFields` listing every invalid field.
+    fn validate(&self) -> Result<(), ApiError>;
+}
+
+/// Collects the failures of a set of rules.
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator { errors: Vec::new() }
+    }
+
+    fn fail(&mut self, field: &str, message: String) -> &mut Validator {
+        self.errors.push(FieldError {
+            field: String::from(field),
+            message: message,
+        });
+        self
+    }
+
+    /// Between `min` and `max` characters.
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Validator {
+        let length = value.chars().count();
+        if length < min || length > max {
+            return self.fail(field, format!("must be between {} and {} characters long", min, max));
+        }
+        self
+    }
+
+    /// At most `max` characters.
+    pub fn max_length(&mut self, field: &str, value: &str, max: usize) -> &mut Validator {
+        if value.chars().count() > max {
+            return self.fail(field, format!("must be at most {} characters long", max));
+        }
+        self
+    }
+
+    /// ASCII letters, digits, `_`, `-` and `.` only, of a bounded length.
+    pub fn username(&mut self, field: &str, value: &str) -> &mut Validator {
+        let valid_chars = value.chars().all(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '_' | '-' | '.' => true,
+            _ => false,
+        });
+        if !valid_chars {
+            return self.fail(field, String::from("may only contain letters, digits, '_', '-' and '.'"));
+        }
+        self.length(field, value, USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH)
+    }
+
+    /// `local@domain.tld`, without whitespace.
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Validator {
+        let mut parts = value.split('@');
+        let is_valid = match (parts.next(), parts.next(), parts.next()) {
+            (Some(local), Some(domain), None) => {
+                !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !value.chars().any(|c| c.is_whitespace())
+            },
+            _ => false,
+        };
+        if !is_valid {
+            return self.fail(field, String::from("must be a valid email address"));
+        }
+        self.max_length(field, value, EMAIL_MAX_LENGTH)
+    }
+
+    /// Long enough, and mixing letters with digits or symbols.
+    pub fn password(&mut self, field: &str, value: &str) -> &mut Validator {
+        let has_letter = value.chars().any(|c| c.is_alphabetic());
+        let has_other = value.chars().any(|c| !c.is_alphabetic());
+        if !has_letter || !has_other {
+            return self.fail(field, String::from("must contain letters and at least one digit or symbol"));
+        }
+        self.length(field, value, PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH)
+    }
+
+    /// A finite number in `[min, max]`.
+    pub fn range(&mut self, field: &str, value: f64, min: f64, max: f64) -> &mut Validator {
+        if !(value >= min && value <= max) { // also catches NaN
+            return self.fail(field, format!("must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn latitude(&mut self, field: &str, value: f64) -> &mut Validator {
+        self.range(field, value, -90.0, 90.0)
+    }
+
+    pub fn longitude(&mut self, field: &str, value: f64) -> &mut Validator {
+        self.range(field, value, -180.0, 180.0)
+    }
+
+    pub fn finish(&mut self) -> Result<(), ApiError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::InvalidFields(self.errors.clone()))
+        }
+    }
+}
+
+impl Validate for db::User {
+    fn validate(&self) -> Result<(), ApiError> {
+        Validator::new()
+            .username("username", &self.username)
+            .email("email", &self.email)
+            .password("password", &self.password)
+            .finish()
+    }
+}
+
+impl Validate for db::PictureMetadata {
+    fn validate(&self) -> Result<(), ApiError> {
+        Validator::new()
+            .max_length("description", &self.description, DESCRIPTION_MAX_LENGTH)
+            .latitude("gps_lat", self.gps_lat)
+            .longitude("gps_long", self.gps_long)
+            .finish()
+    }
+}
+
+impl Validate for db::Rating {
+    fn validate(&self) -> Result<(), ApiError> {
+        Validator::new()
+            .range("score", self.score as f64, RATING_MIN as f64, RATING_MAX as f64)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use error::ApiError;
+    use super::Validator;
+
+    fn repeat(s: &str, n: usize) -> String {
+        iter::repeat(s).take(n).collect()
+    }
+
+    // fields that failed, in order
+    fn failed_fields(validator: &mut Validator) -> Vec<String> {
+        match validator.finish() {
+            Ok(()) => Vec::new(),
+            Err(ApiError::InvalidFields(errors)) => errors.into_iter().map(|error| error.field).collect(),
+            Err(_) => panic!("validation failed with another error"),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_fields() {
+        let fields = failed_fields(Validator::new()
+            .username("username", "jean-luc.p_1")
+            .email("email", "jean@example.com")
+            .password("password", "correct horse 9")
+            .length("nick", "Jean", 1, 50)
+            .max_length("description", "", 500));
+        assert!(fields.is_empty(), "{:?}", fields);
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let fields = failed_fields(Validator::new()
+            .username("username", "jl")
+            .email("email", "jean")
+            .password("password", "short1"));
+        assert_eq!(fields, vec!["username", "email", "password"]);
+    }
+
+    #[test]
+    fn rejects_invalid_usernames() {
+        let too_long = repeat("j", 31);
+        for username in ["jl", "jean luc", "jéan", "jean@luc", &too_long[..]].iter() {
+            assert!(failed_fields(Validator::new().username("username", username)) == vec!["username"], "{}", username);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_emails() {
+        for email in ["", "jean", "@example.com", "jean@example", "jean@.example.com", "jean@example.com.",
+                      "jean@luc@example.com", "jean luc@example.com"].iter() {
+            assert!(failed_fields(Validator::new().email("email", email)) == vec!["email"], "{}", email);
+        }
+
+        let too_long = format!("{}@example.com", repeat("j", 250));
+        assert_eq!(failed_fields(Validator::new().email("email", &too_long)), vec!["email"]);
+    }
+
+    #[test]
+    fn rejects_weak_passwords() {
+        let too_long = format!("a{}", repeat("1", 128));
+        for password in ["", "abc1", "onlyletters", "12345678", "!!!!!!!!", &too_long[..]].iter() {
+            assert!(failed_fields(Validator::new().password("password", password)) == vec!["password"], "{}", password);
+        }
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        assert!(failed_fields(Validator::new().length("nick", "ééé", 1, 3)).is_empty());
+        assert_eq!(failed_fields(Validator::new().length("nick", "", 1, 3)), vec!["nick"]);
+        assert_eq!(failed_fields(Validator::new().max_length("description", "éééé", 3)), vec!["description"]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_coordinates() {
+        assert!(failed_fields(Validator::new().latitude("gps_lat", 90.0).longitude("gps_long", -180.0)).is_empty());
+        let fields = failed_fields(Validator::new()
+            .latitude("gps_lat", 90.5)
+            .longitude("gps_long", ::std::f64::NAN));
+        assert_eq!(fields, vec!["gps_lat", "gps_long"]);
+    }
+}